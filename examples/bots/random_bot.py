@@ -0,0 +1,24 @@
+#!/usr/bin/env python3
+# A minimal bot for the engine protocol (see src/engine.rs).
+# Try it with: battleship-sim --strategy "engine:python3 examples/bots/random_bot.py"
+import random
+import sys
+
+cells = []
+for line in sys.stdin:
+    cmd, *args = line.split()
+    if cmd == "battleship":
+        print("ready random-py")
+    elif cmd == "newgame":
+        cols, rows = int(args[0]), int(args[1])
+        cells = ["ABCDEFGHI"[x] + str(y + 1) for x in range(cols) for y in range(rows)]
+        random.shuffle(cells)
+    elif cmd == "place":
+        print("auto")
+    elif cmd == "shoot":
+        print("fire " + cells.pop())
+    elif cmd == "result":
+        print("info " + " ".join(args))
+    elif cmd == "quit":
+        break
+    sys.stdout.flush()
@@ -0,0 +1,54 @@
+// Places ships and fires at random, checking the board never contradicts itself.
+#![no_main]
+
+use std::collections::HashSet;
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use strum::IntoEnumIterator;
+
+use libbattleship::{Board, Direction, DirectionName, Position, ShipType};
+
+#[derive(Arbitrary, Debug)]
+enum Move {
+    Place(u8, i32, i32, u8),
+    Auto,
+    Fire(i32, i32),
+}
+
+fuzz_target!(|moves: Vec<Move>| {
+    let ships: Vec<ShipType> = ShipType::iter().collect();
+    let directions: Vec<DirectionName> = DirectionName::iter().collect();
+    let mut board = Board::new();
+    let mut fired = HashSet::new();
+    let mut progress = 0.0;
+    for m in moves {
+        match m {
+            Move::Place(ship, x, y, dir) => {
+                let ship = ships[ship as usize % ships.len()];
+                let dir = Direction::new(directions[dir as usize % directions.len()]);
+                let _ = board.place_ship(ship, &Position::new(x, y), &dir);
+                progress = board.get_progress();
+            }
+            Move::Auto => {
+                board.place_remaining_auto();
+                progress = board.get_progress();
+            }
+            Move::Fire(x, y) => {
+                let pos = Position::new(x, y);
+                let hit = board.hit_cell(pos);
+                let fresh = board.is_valid_position(&pos) && fired.insert(pos);
+                assert_eq!(hit, board.get_occupied_cells().contains(&pos) && fresh);
+                assert!(board.get_progress() >= progress);
+                progress = board.get_progress();
+            }
+        }
+        // No overlaps: the cells taken are exactly the sizes of the ships placed.
+        let unplaced = board.get_unplaced_ships();
+        let size: usize = ships.iter().filter(|s| !unplaced.contains(s)).map(|s| s.get_size() as usize).sum();
+        assert_eq!(board.get_occupied_cells().len(), size);
+        // The game is over exactly when every ship cell has been fired at.
+        let sunk = board.get_occupied_cells().iter().all(|pos| fired.contains(pos));
+        assert_eq!(board.is_game_complete(), size > 0 && sunk);
+    }
+});
@@ -0,0 +1,16 @@
+// Everything that reads text from a client or a file must take any input.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use libbattleship::command_parser;
+use libbattleship::config::Config;
+use libbattleship::layout::parse_layout;
+
+fuzz_target!(|text: &str| {
+    for line in text.lines() {
+        command_parser(line);
+    }
+    let _ = parse_layout(text);
+    let _ = Config::parse(text);
+});
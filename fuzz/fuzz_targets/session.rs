@@ -0,0 +1,27 @@
+// Two clients sharing a lobby, each line going to one or the other.
+#![no_main]
+
+use std::sync::mpsc;
+
+use libfuzzer_sys::fuzz_target;
+
+use libbattleship::server::{Lobby, Session};
+
+fuzz_target!(|text: &str| {
+    let lobby = Lobby::shared();
+    let (outbox, _inbox) = mpsc::channel();
+    let mut sessions = [Session::new(lobby.clone(), outbox.clone()), Session::new(lobby.clone(), outbox)];
+    for (i, line) in text.lines().enumerate() {
+        let session = &mut sessions[i % 2];
+        // Account commands are left out: hashing a password is far too slow to fuzz.
+        if line.starts_with("REGISTER") || line.starts_with("LOGIN") {
+            continue
+        }
+        if !session.handle_line(line) {
+            break
+        }
+    }
+    for session in sessions.iter_mut() {
+        session.close();
+    }
+});
@@ -0,0 +1,191 @@
+// Player accounts: a name and a password, kept in a JSON file as a random
+// salt and a PBKDF2-HMAC-SHA256 hash of the password. Passwords themselves
+// are never stored.
+//
+// Hashing is slow on purpose, so the server does it outside the lock on the
+// store: Credentials are made or checked on their own, and only looked up in
+// or added to the store under the lock.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+
+pub const MIN_PASSWORD_LEN: usize = 6;
+const HASH_ROUNDS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Credentials {
+    salt: String,  // Hex encoded
+    hash: String,
+    rounds: u32,
+}
+
+impl Credentials {
+    pub fn new(password: &str, rounds: u32) -> Credentials {
+        let salt = to_hex(&rand::thread_rng().gen::<[u8; SALT_LEN]>());
+        let hash = hash_password(password, &salt, rounds);
+        Credentials{salt, hash, rounds}
+    }
+
+    pub fn check(&self, password: &str) -> bool {
+        same(&hash_password(password, &self.salt, self.rounds), &self.hash)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Account {
+    name: String,
+    #[serde(flatten)]
+    credentials: Credentials,
+}
+
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hash_password(password: &str, salt: &str, rounds: u32) -> String {
+    let mut out = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt.as_bytes(), rounds, &mut out);
+    to_hex(&out)
+}
+
+// Compares every byte whatever the first difference, so timing tells nothing.
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+
+pub struct AccountStore {
+    path: Option<PathBuf>,  // None keeps accounts in memory
+    accounts: HashMap<String, Account>,
+    rounds: u32,
+}
+
+impl AccountStore {
+    pub fn in_memory() -> AccountStore {
+        AccountStore{path: None, accounts: HashMap::new(), rounds: HASH_ROUNDS}
+    }
+
+    // Loads the accounts from `path`, which is created with the first account if missing.
+    pub fn open(path: &Path) -> Result<AccountStore, String> {
+        let accounts: Vec<Account> = match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e))
+        };
+        let accounts = accounts.into_iter().map(|a| (a.name.clone(), a)).collect();
+        Ok(AccountStore{path: Some(path.to_path_buf()), accounts, rounds: HASH_ROUNDS})
+    }
+
+    // Fewer hashing rounds make tests quicker. Existing accounts keep theirs.
+    pub fn set_rounds(&mut self, rounds: u32) {
+        self.rounds = rounds;
+    }
+
+    pub fn get_rounds(&self) -> u32 {
+        self.rounds
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(())
+        };
+        let mut accounts: Vec<&Account> = self.accounts.values().collect();
+        accounts.sort_by(|a, b| a.name.cmp(&b.name));
+        let json = serde_json::to_string_pretty(&accounts).unwrap();
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, json).map_err(|e| format!("{}: {}", temp.display(), e))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&temp, fs::Permissions::from_mode(0o600)).ok();
+        }
+        fs::rename(&temp, path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.accounts.contains_key(name)
+    }
+
+    pub fn check_password(password: &str) -> Result<(), String> {
+        if password.chars().count() < MIN_PASSWORD_LEN {
+            return Err(format!("Password too short (at least {} characters).", MIN_PASSWORD_LEN))
+        }
+        Ok(())
+    }
+
+    pub fn register(&mut self, name: &str, password: &str) -> Result<(), String> {
+        if self.exists(name) {
+            return Err(String::from("Name already taken."))
+        }
+        Self::check_password(password)?;
+        self.insert(name, Credentials::new(password, self.rounds))
+    }
+
+    // Adds an account whose password was hashed beforehand.
+    pub fn insert(&mut self, name: &str, credentials: Credentials) -> Result<(), String> {
+        if self.exists(name) {
+            return Err(String::from("Name already taken."))
+        }
+        self.accounts.insert(name.to_string(), Account{name: name.to_string(), credentials});
+        if let Err(e) = self.save() {
+            self.accounts.remove(name);
+            return Err(e)
+        }
+        Ok(())
+    }
+
+    pub fn get_credentials(&self, name: &str) -> Option<Credentials> {
+        self.accounts.get(name).map(|account| account.credentials.clone())
+    }
+
+    pub fn verify(&self, name: &str, password: &str) -> bool {
+        self.accounts.get(name).is_some_and(|account| account.credentials.check(password))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_are_salted_and_checked() {
+        let path = std::env::temp_dir().join(format!("battleship-accounts-{}.json", std::process::id()));
+        fs::remove_file(&path).ok();
+        let mut store = AccountStore::open(&path).unwrap();
+        store.set_rounds(10);
+        store.register("ann", "secret1").unwrap();
+        store.register("bob", "secret1").unwrap();
+        assert!(store.register("ann", "another").is_err());
+        assert!(store.register("cid", "short").is_err());
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(!text.contains("secret1"));
+        let store = AccountStore::open(&path).unwrap();
+        assert_ne!(store.accounts["ann"].credentials.hash, store.accounts["bob"].credentials.hash);
+        assert!(text.contains("\"rounds\": 10"));
+        assert!(store.verify("ann", "secret1"));
+        assert!(!store.verify("ann", "secret2"));
+        assert!(!store.verify("cid", "secret1"));
+
+        // Hashed apart from the store, as the server does.
+        let mut store = store;
+        let credentials = Credentials::new("secret3", 10);
+        assert!(credentials.check("secret3") && !credentials.check("secret1"));
+        assert!(store.insert("ann", credentials.clone()).is_err());
+        store.insert("cid", credentials).unwrap();
+        assert!(store.get_credentials("cid").unwrap().check("secret3"));
+        fs::remove_file(&path).ok();
+    }
+}
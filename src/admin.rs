@@ -0,0 +1,220 @@
+// Admin console: a Unix socket only the server's user can open, speaking
+// a line protocol of its own (try `nc -U battleship-admin.sock`):
+//
+//   GAMES               running games, one per line
+//   CLIENTS             connections, one per line
+//   KICK <conn>         disconnects a client
+//   END <game>          stops a game, nobody wins
+//   BROADCAST <text>    NOTICE to every client
+//   RELOAD              reads the config file again (see config.rs)
+//   SHUTDOWN [seconds]  warns everybody, waits, then stops the server
+//
+// Multi line answers start with a header giving the number of lines to follow.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::log_event;
+use crate::server::{self, ConnId, GameId, SharedLobby};
+
+
+#[derive(Debug, PartialEq)]
+pub enum AdminCommand {
+    Games,
+    Clients,
+    Kick(ConnId),
+    End(GameId),
+    Broadcast(String),
+    Reload,
+    Shutdown(u64),
+    Invalid,
+}
+
+pub fn parse(line: &str) -> AdminCommand {
+    let line = line.trim();
+    let (cmd, arg) = match line.split_once(' ') {
+        Some((cmd, arg)) => (cmd, Some(arg.trim())),
+        None => (line, None)
+    };
+    match (cmd.to_uppercase().as_str(), arg) {
+        ("GAMES", None) => AdminCommand::Games,
+        ("CLIENTS", None) => AdminCommand::Clients,
+        ("KICK", Some(conn)) => conn.parse().map_or(AdminCommand::Invalid, AdminCommand::Kick),
+        ("END", Some(game)) => game.parse().map_or(AdminCommand::Invalid, AdminCommand::End),
+        ("BROADCAST", Some(text)) if !text.is_empty() => AdminCommand::Broadcast(server::sanitize_chat(text)),
+        ("RELOAD", None) => AdminCommand::Reload,
+        ("SHUTDOWN", None) => AdminCommand::Shutdown(0),
+        ("SHUTDOWN", Some(grace)) => grace.parse().map_or(AdminCommand::Invalid, AdminCommand::Shutdown),
+        _ => AdminCommand::Invalid
+    }
+}
+
+
+pub struct Admin {
+    lobby: SharedLobby,
+    config: Option<PathBuf>,  // File RELOAD reads
+}
+
+impl Admin {
+    pub fn new(lobby: SharedLobby, config: Option<PathBuf>) -> Admin {
+        Admin{lobby, config}
+    }
+
+    // Answers a command. SHUTDOWN is only acknowledged here; serve carries it out.
+    pub fn handle(&self, cmd: &AdminCommand) -> String {
+        let mut lobby = self.lobby.lock().unwrap();
+        match cmd {
+            AdminCommand::Games => lobby.games_report(),
+            AdminCommand::Clients => lobby.clients_report(),
+            AdminCommand::Kick(conn) => {
+                if lobby.kick(*conn, "Disconnected by the server.") {format!("Kicked {}.", conn)} else {String::from("No such client.")}
+            }
+            AdminCommand::End(id) => {
+                if lobby.end_game(*id) {format!("Game {} ended.", id)} else {String::from("No such game.")}
+            }
+            AdminCommand::Broadcast(text) => {
+                lobby.broadcast(text);
+                format!("Sent to {} clients.", lobby.get_client_count())
+            }
+            AdminCommand::Reload => {
+                let path = match &self.config {
+                    Some(path) => path,
+                    None => return String::from("No config file given (start the server with --config <file>).")
+                };
+                match Config::load(path) {
+                    Ok(config) => {
+                        lobby.apply_config(&config);
+                        format!("Reloaded {}.", path.display())
+                    }
+                    Err(e) => format!("Config not reloaded: {}", e)
+                }
+            }
+            AdminCommand::Shutdown(grace) => format!("Shutting down in {} seconds.", grace),
+            AdminCommand::Invalid => String::from("Commands: GAMES, CLIENTS, KICK <conn>, END <game>, BROADCAST <text>, RELOAD, SHUTDOWN [seconds]")
+        }
+    }
+}
+
+
+// Listens on `path`, replacing a socket left behind by an earlier run.
+// The socket is bound inside a directory only we can enter and moved into
+// place once its permissions are right, so nobody else can connect meanwhile.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+    let dir = path.with_file_name(format!(".{}.{}", name.to_string_lossy(), process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let bound = UnixListener::bind(dir.join("sock")).and_then(|listener| {
+        fs::set_permissions(dir.join("sock"), fs::Permissions::from_mode(0o600))?;
+        fs::rename(dir.join("sock"), path)?;
+        Ok(listener)
+    });
+    fs::remove_file(dir.join("sock")).ok();
+    fs::remove_dir(&dir)?;
+    bound
+}
+
+fn session(stream: UnixStream, admin: &Admin) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let cmd = parse(&line?);
+        log_event!(Info, "admin", cmd = format!("{:?}", cmd));
+        writeln!(writer, "{}", admin.handle(&cmd))?;
+        if let AdminCommand::Shutdown(grace) = cmd {
+            server::shutdown(&admin.lobby, Duration::from_secs(grace));
+            log_event!(Info, "shutdown");
+            process::exit(0);
+        }
+    }
+    Ok(())
+}
+
+pub fn serve(listener: UnixListener, admin: Admin) {
+    let admin = Arc::new(admin);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let admin = admin.clone();
+                thread::spawn(move || session(stream, &admin));
+            }
+            Err(e) => log_event!(Warn, "accept_failed", error = e)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use crate::server::{Lobby, Session};
+
+    #[test]
+    fn commands_are_parsed() {
+        assert_eq!(parse("games"), AdminCommand::Games);
+        assert_eq!(parse("KICK 3"), AdminCommand::Kick(3));
+        assert_eq!(parse("KICK me"), AdminCommand::Invalid);
+        assert_eq!(parse("BROADCAST back in 5"), AdminCommand::Broadcast(String::from("back in 5")));
+        assert_eq!(parse("SHUTDOWN"), AdminCommand::Shutdown(0));
+        assert_eq!(parse("SHUTDOWN 30"), AdminCommand::Shutdown(30));
+    }
+
+    #[test]
+    fn operators_manage_the_lobby() {
+        let lobby = Lobby::shared();
+        let config = std::env::temp_dir().join(format!("battleship-config-{}.conf", std::process::id()));
+        fs::write(&config, "hints = 1\n").unwrap();
+        let admin = Admin::new(lobby.clone(), Some(config.clone()));
+        let (outbox, inbox) = mpsc::channel();
+        let mut host = Session::new(lobby.clone(), outbox);
+        host.handle_line("NEWMATCH");
+        inbox.try_recv().unwrap();
+
+        assert_eq!(admin.handle(&AdminCommand::Games), "GAMES 1\n1 match waiting 1:- spectators=0 shots=0");
+        assert_eq!(admin.handle(&AdminCommand::Clients), "CLIENTS 1\n1 - - 1");
+        assert_eq!(admin.handle(&AdminCommand::Broadcast(String::from("hello"))), "Sent to 1 clients.");
+        assert_eq!(inbox.try_recv().unwrap(), "NOTICE hello");
+        assert_eq!(admin.handle(&AdminCommand::End(1)), "Game 1 ended.");
+        assert_eq!(inbox.try_recv().unwrap(), "ENDED 1");
+        assert_eq!(admin.handle(&AdminCommand::End(1)), "No such game.");
+
+        assert!(admin.handle(&AdminCommand::Reload).starts_with("Reloaded "));
+        host.handle_line("STARTGAME");
+        inbox.try_recv().unwrap();
+        host.handle_line("HINT");
+        assert!(inbox.try_recv().unwrap().starts_with("HINT "));
+        host.handle_line("HINT");
+        assert_eq!(inbox.try_recv().unwrap(), "No hints left (1 per game).");
+
+        assert_eq!(admin.handle(&AdminCommand::Kick(1)), "Kicked 1.");
+        assert_eq!(inbox.try_recv().unwrap(), "NOTICE Disconnected by the server.");
+        assert_eq!(admin.handle(&AdminCommand::Kick(9)), "No such client.");
+        fs::remove_file(&config).ok();
+    }
+
+    #[test]
+    fn the_socket_is_private() {
+        let path = std::env::temp_dir().join(format!("battleship-admin-{}.sock", std::process::id()));
+        let listener = bind(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let dir = format!(".{}.{}", path.file_name().unwrap().to_string_lossy(), std::process::id());
+        assert!(!path.with_file_name(dir).exists());
+        UnixStream::connect(&path).unwrap();
+        listener.accept().unwrap();
+
+        // A socket left behind is replaced.
+        drop(listener);
+        bind(&path).unwrap();
+        fs::remove_file(&path).ok();
+    }
+}
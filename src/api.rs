@@ -0,0 +1,249 @@
+// HTTP interface for dashboards and bots, on its own port. Bodies are JSON.
+//
+//   GET  /games                 running games
+//   POST /games                 starts a game against a computer placed fleet:
+//                               {"id": 4, "token": "..."}
+//   GET  /games/<id>            a game and what is known of each fleet
+//   POST /games/<id>/shots      {"cell": "B1"} or {"x": 2, "y": 1}, with the
+//                               header `Authorization: Bearer <token>`
+//   GET  /games/<id>/replay     every shot so far, and both fleets once it is over
+//
+// Games are the lobby's, so those played over TCP show up too, but only
+// games started here can be shot at here. Errors come back as {"error": "..."}.
+// At most MAX_API_GAMES of them run at once (503 past that), and one left
+// without a shot for API_GAME_IDLE is ended. Bodies over MAX_BODY bytes get 413.
+
+use std::io::Read;
+use std::net::TcpListener;
+use std::thread;
+use std::time::Instant;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{CommandResult, GameCommand, Position, command_handler, log_event};
+use crate::server::{Game, GameId, SharedLobby};
+
+
+const MAX_BODY: usize = 4096;
+
+
+#[derive(Deserialize, Debug, Default)]
+struct ShotRequest {
+    cell: Option<String>,
+    x: Option<i32>,
+    y: Option<i32>,
+}
+
+impl ShotRequest {
+    fn position(&self) -> Option<Position> {
+        match (&self.cell, self.x, self.y) {
+            (Some(cell), None, None) => Position::from_coord(cell),
+            (None, Some(x), Some(y)) if (1..=9).contains(&x) && (1..=9).contains(&y) => Some(Position::new(x, y)),
+            _ => None
+        }
+    }
+}
+
+
+fn error(status: u16, msg: &str) -> (u16, Value) {
+    (status, json!({"error": msg}))
+}
+
+fn summary(game: &Game) -> Value {
+    json!({
+        "id": game.get_id(),
+        "solo": game.is_solo(),
+        "started": game.is_started(),
+        "underway": game.is_underway(),
+        "players": game.get_player_names(),
+        "spectators": game.get_spectator_count(),
+    })
+}
+
+// Each side's board as a list of rows, in the notation of FOG lines,
+// or null for the player of a single player game, who has no fleet.
+fn fog(game: &Game) -> Vec<Option<Vec<String>>> {
+    (1..=game.get_player_names().len())
+        .map(|side| game.get_fog(side).map(|board| board.split('/').map(String::from).collect()))
+        .collect()
+}
+
+
+fn create_game(lobby: &SharedLobby) -> (u16, Value) {
+    match command_handler(&mut None, GameCommand::StartGame) {
+        CommandResult::Some(fleet) => {
+            match lobby.lock().unwrap().add_api_game(fleet) {
+                Ok((id, token)) => (201, json!({"id": id, "token": token})),
+                Err(msg) => error(503, &msg)
+            }
+        }
+        _ => error(500, "Could not set up a fleet.")
+    }
+}
+
+fn shoot(lobby: &SharedLobby, id: GameId, token: Option<&str>, body: &str) -> (u16, Value) {
+    let shot: ShotRequest = match serde_json::from_str(body) {
+        Ok(shot) => shot,
+        Err(e) => return error(400, &format!("Invalid body: {}", e))
+    };
+    let pos = match shot.position() {
+        Some(pos) => pos,
+        None => return error(400, "Give either a cell such as \"B1\" or x and y from 1 to 9.")
+    };
+    let mut lobby = lobby.lock().unwrap();
+    match lobby.get_game(id) {
+        None => return error(404, "No such game."),
+        Some(game) if !token.is_some_and(|t| game.check_token(t)) => return error(403, "Wrong or missing game token."),
+        Some(_) => {}
+    }
+    let result = lobby.fire(id, 0, pos.x, pos.y, None);
+    match result {
+        CommandResult::Success(_) => (200, json!({"cell": pos.to_string(), "result": "HIT", "game_over": false})),
+        CommandResult::Failure(msg) if msg == "MISS" => (200, json!({"cell": pos.to_string(), "result": "MISS", "game_over": false})),
+        CommandResult::GameComplete(score) => {
+            (200, json!({"cell": pos.to_string(), "result": "HIT", "game_over": true, "score": score}))
+        }
+        CommandResult::Failure(msg) | CommandResult::Message(msg) => error(409, &msg),
+        _ => error(500, "Unexpected result.")
+    }
+}
+
+// Answers one request, as a status code and a JSON body.
+pub fn handle(lobby: &SharedLobby, method: &str, path: &str, token: Option<&str>, body: &str) -> (u16, Value) {
+    let parts: Vec<&str> = path.split('?').next().unwrap_or("").trim_matches('/').split('/').collect();
+    let id = parts.get(1).and_then(|id| id.parse::<GameId>().ok());
+    match (method, parts.as_slice(), id) {
+        ("GET", ["games"], _) => {
+            let mut lobby = lobby.lock().unwrap();
+            lobby.expire_idle_games(Instant::now());
+            let games: Vec<Value> = lobby.get_games().into_iter().map(summary).collect();
+            (200, json!({"games": games}))
+        }
+        ("POST", ["games"], _) => create_game(lobby),
+        ("GET", ["games", _], Some(id)) => {
+            let lobby = lobby.lock().unwrap();
+            match lobby.get_game(id) {
+                Some(game) => {
+                    let mut out = summary(game);
+                    out["turn"] = json!(game.get_turn());
+                    out["fog"] = json!(fog(game));
+                    (200, out)
+                }
+                None => error(404, "No such game.")
+            }
+        }
+        ("POST", ["games", _, "shots"], Some(id)) => shoot(lobby, id, token, body),
+        ("GET", ["games", _, "replay"], Some(id)) => match lobby.lock().unwrap().get_replay(id) {
+            Some(replay) => (200, json!(replay)),
+            None => error(404, "No such game.")
+        },
+        (_, ["games"], _) | (_, ["games", _], Some(_)) | (_, ["games", _, "shots"], Some(_))
+            | (_, ["games", _, "replay"], Some(_)) => error(405, "Method not allowed."),
+        _ => error(404, "Not found.")
+    }
+}
+
+
+fn bearer(request: &tiny_http::Request) -> Option<String> {
+    let header = request.headers().iter().find(|h| h.field.equiv("Authorization"))?;
+    header.value.as_str().strip_prefix("Bearer ").map(|t| t.trim().to_string())
+}
+
+// Reads a body of at most MAX_BODY bytes.
+fn read_body<R: Read>(reader: R) -> Result<String, (u16, Value)> {
+    let mut body = String::new();
+    match reader.take(MAX_BODY as u64 + 1).read_to_string(&mut body) {
+        Ok(n) if n > MAX_BODY => Err(error(413, "Body too large.")),
+        Ok(_) => Ok(body),
+        Err(_) => Err(error(400, "Body is not UTF-8."))
+    }
+}
+
+fn answer(mut request: tiny_http::Request, lobby: &SharedLobby) {
+    let too_large = request.body_length().is_some_and(|n| n > MAX_BODY);
+    let read = if too_large {Err(error(413, "Body too large."))} else {read_body(request.as_reader())};
+    let (status, reply) = match read {
+        Ok(body) => handle(lobby, request.method().as_str(), request.url(), bearer(&request).as_deref(), &body),
+        Err(refused) => refused
+    };
+    log_event!(Info, "http", method = request.method(), path = request.url(), status = status);
+    let content_type = tiny_http::Header::from_bytes("Content-Type", "application/json").unwrap();
+    let response = tiny_http::Response::from_string(reply.to_string())
+        .with_status_code(status)
+        .with_header(content_type);
+    request.respond(response).ok();
+}
+
+// Each request is answered on its own thread, so a slow client holds up nobody else.
+pub fn serve(listener: TcpListener, lobby: SharedLobby) {
+    let server = match tiny_http::Server::from_listener(listener, None) {
+        Ok(server) => server,
+        Err(e) => return log_event!(Error, "api_failed", error = e)
+    };
+    for request in server.incoming_requests() {
+        let lobby = lobby.clone();
+        thread::spawn(move || answer(request, &lobby));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Lobby;
+
+    #[test]
+    fn bots_play_over_http() {
+        let lobby = Lobby::shared();
+        let (status, created) = handle(&lobby, "POST", "/games", None, "");
+        assert_eq!(status, 201);
+        let id = created["id"].as_u64().unwrap();
+        let token = created["token"].as_str().unwrap();
+
+        let (status, list) = handle(&lobby, "GET", "/games", None, "");
+        assert_eq!(status, 200);
+        assert_eq!(list["games"][0]["id"], id);
+
+        let shots = format!("/games/{}/shots", id);
+        assert_eq!(handle(&lobby, "POST", &shots, None, r#"{"cell": "A1"}"#).0, 403);
+        assert_eq!(handle(&lobby, "POST", &shots, Some(token), r#"{"cell": "Z1"}"#).0, 400);
+
+        let (status, first) = handle(&lobby, "POST", &shots, Some(token), r#"{"cell": "A1"}"#);
+        assert_eq!(status, 200);
+        let (_, game) = handle(&lobby, "GET", &format!("/games/{}", id), None, "");
+        assert_eq!(game["fog"][0], Value::Null);
+        let seen = if first["result"] == "HIT" {"X"} else {"o"};
+        assert!(game["fog"][1][0].as_str().unwrap().starts_with(seen));
+
+        // Sweep the rest of the board until the fleet goes down.
+        let mut last = first;
+        'sweep: for y in 1..=9 {
+            for x in (1..=9).filter(|x| (*x, y) != (1, 1)) {
+                let (status, reply) = handle(&lobby, "POST", &shots, Some(token), &format!(r#"{{"x": {}, "y": {}}}"#, x, y));
+                assert_eq!(status, 200);
+                last = reply;
+                if last["game_over"] == true {
+                    break 'sweep
+                }
+            }
+        }
+        assert!(last["score"].as_i64().unwrap() >= 0);
+        assert_eq!(handle(&lobby, "GET", &format!("/games/{}", id), None, "").0, 404);
+
+        let (status, replay) = handle(&lobby, "GET", &format!("/games/{}/replay", id), None, "");
+        assert_eq!(status, 200);
+        assert_eq!(replay["shots"][0], json!({"player": 1, "cell": "A1", "hit": replay["shots"][0]["hit"]}));
+        assert_eq!(replay["winner"], 1);
+        assert_eq!(replay["fleets"].as_array().unwrap().len(), 1);
+        assert_eq!(handle(&lobby, "DELETE", "/games", None, "").0, 405);
+    }
+
+    #[test]
+    fn large_bodies_are_refused() {
+        assert_eq!(read_body(&b"{\"cell\": \"A1\"}"[..]).unwrap(), r#"{"cell": "A1"}"#);
+        assert_eq!(read_body(&vec![b' '; MAX_BODY][..]).unwrap().len(), MAX_BODY);
+        assert_eq!(read_body(&vec![b' '; MAX_BODY + 1][..]).unwrap_err().0, 413);
+        assert_eq!(read_body(&[0xff, 0xfe][..]).unwrap_err().0, 400);
+    }
+}
@@ -1,12 +1,236 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Path;
 use std::net::TcpStream;
-use std::io::{self, BufRead, Write, BufReader, BufWriter, Error};
+use std::io::{self, BufRead, Write, BufReader, Error};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use libbattleship::{BoardConfig, Position};
+use libbattleship::layout::{layout_lines, parse_layout};
+use libbattleship::puzzle::{Difficulty, Puzzle};
+use libbattleship::tls::{self, Stream, TlsStream};
+
+
+// Turns a line from the server into something nicer to read.
+// Boards are drawn as a grid, everything else is printed as is.
+fn render(line: &str) -> String {
+    let parts: Vec<&str> = line.splitn(4, ' ').collect();
+    match parts.as_slice() {
+        ["SHOT", game, player, rest] => format!("[game {}] Player {} fired at {}", game, player, rest.replacen(' ', ": ", 1)),
+        ["JOINED", game, player] => format!("[game {}] Player {} joined.", game, player),
+        ["READY", game, player] => format!("[game {}] Player {} has placed their fleet.", game, player),
+        ["GAMEOVER", game, player] => format!("[game {}] Game over, player {} wins.", game, player),
+        ["CHAT", game, player, text] => format!("[game {}] Player {} says: {}", game, player, text),
+        ["ENDED", game] => format!("[game {}] Game stopped by the server.", game),
+        ["SAVED", game, token] => format!("[game {}] Game saved. Once the server is back, RESUME {} {} to carry on.", game, game, token),
+        ["NOTICE", ..] => format!("Server notice: {}", line.strip_prefix("NOTICE").unwrap_or("").trim()),
+        [kind @ "FOG", game, player, board] | [kind @ "REVEAL", game, player, board] => {
+            let title = if *kind == "FOG" {"Known"} else {"Revealed"};
+            format!("[game {}] {} fleet of player {}:{}", game, title, player, grid(board))
+        }
+        ["HINT", cell, left, heatmap] => {
+            format!("Hint: try {} ({} hints left). Chance of a ship, in tenths:{}", cell, left, grid(heatmap))
+        }
+        _ => format!("Server: {}", line)
+    }
+}
+
+
+fn grid(board: &str) -> String {
+    let mut out = String::from("\n   A B C D E F G H I");
+    for (i, row) in board.split('/').enumerate() {
+        out.push_str(&format!("\n{}  ", i+1));
+        for c in row.chars() {
+            out.push(c);
+            out.push(' ');
+        }
+    }
+    out
+}
+
+
+fn send<S: Stream>(writer: &Mutex<S>, msg: &str) -> Result<(), Error> {
+    let mut writer = writer.lock().unwrap();
+    writer.write_all(format!("{}\n", msg).as_bytes())?;
+    writer.flush()
+}
+
+
+// Prints whatever the server sends as soon as it arrives, replies and pushed events alike.
+// With a layout, the fleet is submitted once both players are in a match this
+// client has just created or joined, which both of them are told with a JOINED
+// line. A resumed game already has its fleet.
+fn read_loop<S: Stream>(stream: S, writer: Arc<Mutex<S>>, layout: Option<Vec<String>>) -> Result<(), Error> {
+    let mut reader = BufReader::new(stream);
+    let mut waiting: HashSet<String> = HashSet::new();
+    loop {
+        let mut buff = String::new();
+        if reader.read_line(&mut buff)? == 0 {
+            return Ok(())
+        }
+        let line = buff.trim();
+        println!("\r{}", render(line));
+        match line.split(' ').collect::<Vec<&str>>().as_slice() {
+            ["Match", game, "created.", ..] | ["Joined", "match", game, ..] => {
+                waiting.insert(game.trim_end_matches('.').to_string());
+            }
+            ["JOINED", game, _] => {
+                if let (Some(layout), true) = (&layout, waiting.remove(*game)) {
+                    println!("Submitting fleet layout.");
+                    for ship in layout {
+                        send(&writer, &format!("PLACE {}", ship))?;
+                    }
+                    send(&writer, "READY")?;
+                }
+            }
+            _ => {}
+        }
+        print!("> ");
+        io::stdout().flush()?;
+    }
+}
+
+
+const PUZZLE_HELP: &str = "Mark cells with SHIP <cell>, WATER <cell> or CLEAR <cell> (e.g. SHIP C4).
+CHECK tells whether the fleet is right, SOLVE shows the answer, EXIT goes back.";
+
+
+// Battleship solitaire, played locally: find the fleet from the row and column counts.
+fn play_puzzle(difficulty: &str) -> Result<(), Error> {
+    let difficulty: Difficulty = match difficulty.parse() {
+        Ok(difficulty) => difficulty,
+        Err(_) => {
+            println!("Difficulty must be easy, medium or hard.");
+            return Ok(())
+        }
+    };
+    let puzzle = Puzzle::generate(difficulty, &mut rand::thread_rng());
+    let mut ships: HashSet<Position> = HashSet::new();
+    let mut water: HashSet<Position> = HashSet::new();
+    println!("{} puzzle. Fleet: C5 H4 L3 A2, no two ships touching, not even diagonally.\n{}", difficulty, PUZZLE_HELP);
+    loop {
+        println!("{}", puzzle.display(&ships, &water));
+        print!("puzzle> ");
+        io::stdout().flush()?;
+        let mut inpt = String::new();
+        if io::stdin().read_line(&mut inpt)? == 0 {
+            return Ok(())
+        }
+        let words: Vec<String> = inpt.split_whitespace().map(|w| w.to_uppercase()).collect();
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        match words.as_slice() {
+            ["EXIT"] => return Ok(()),
+            ["CHECK"] => {
+                if puzzle.check(&ships) {
+                    println!("Solved!");
+                    return Ok(())
+                }
+                println!("Not quite.");
+            }
+            ["SOLVE"] => {
+                println!("{}", puzzle.display(&puzzle.get_solution(), &HashSet::new()));
+                return Ok(())
+            }
+            [mark @ "SHIP", cell] | [mark @ "WATER", cell] | [mark @ "CLEAR", cell] => {
+                match Position::from_coord(cell) {
+                    Some(pos) if puzzle.is_given(&pos) => println!("{} is given.", pos),
+                    Some(pos) => {
+                        ships.remove(&pos);
+                        water.remove(&pos);
+                        match *mark {
+                            "SHIP" => {ships.insert(pos);}
+                            "WATER" => {water.insert(pos);}
+                            _ => {}
+                        }
+                    }
+                    None => println!("No such cell: {}", cell)
+                }
+            }
+            _ => println!("{}", PUZZLE_HELP)
+        }
+    }
+}
+
+
+const SERVER: &str = "localhost:8888";
+
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1);
+}
+
+
+// Value following `flag` on the command line, if the flag is there.
+fn option<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    let i = args.iter().position(|a| a == flag)?;
+    match args.get(i+1) {
+        Some(value) => Some(value),
+        None => fail(&format!("{} needs a value", flag))
+    }
+}
+
+
+// Reads the fleet layout given with --layout <file>, if any.
+fn load_layout(args: &[String]) -> Result<Option<Vec<String>>, String> {
+    let path = match option(args, "--layout") {
+        Some(path) => path,
+        None => return Ok(None)
+    };
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    match parse_layout(&text).map_err(|e| format!("{}: {}", path, e))? {
+        BoardConfig::Manual(layout) => Ok(Some(layout_lines(&layout))),
+        _ => Ok(None)
+    }
+}
 
 
 fn main() -> Result<(), Error> {
-    let stream = TcpStream::connect("localhost:8888")?;
+    let args: Vec<String> = env::args().collect();
+    if let Some(i) = args.iter().position(|a| a == "--puzzle") {
+        // Solitaire needs no server.
+        return play_puzzle(args.get(i+1).map(String::as_str).unwrap_or("medium"))
+    }
+
+    let layout = match load_layout(&args) {
+        Ok(layout) => layout,
+        Err(msg) => {
+            eprintln!("Invalid layout {}", msg);
+            process::exit(1);
+        }
+    };
+
+    let server = option(&args, "--server").unwrap_or(SERVER);
+    let stream = TcpStream::connect(server)?;
     println!("Successfully Connected to {}", stream.peer_addr()?);
-    let mut reader = BufReader::new(&stream);
-    let mut writer = BufWriter::new(&stream);
+    match option(&args, "--tls-ca") {
+        Some(ca) => {
+            // The server's certificate must be signed by this CA and name the host we asked for.
+            let config = tls::client_config(Path::new(ca)).unwrap_or_else(|e| fail(&format!("Cannot set up TLS: {}", e)));
+            let host = server.rsplit_once(':').map_or(server, |(host, _)| host);
+            let name = option(&args, "--tls-name").unwrap_or(host);
+            play(TlsStream::connect(stream, name, config)?, layout)
+        }
+        None => play(stream, layout)
+    }
+}
+
+
+// Talks to the server until either side hangs up, over a plain or a TLS stream.
+fn play<S: Stream>(stream: S, layout: Option<Vec<String>>) -> Result<(), Error> {
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+
+    let reader_stream = stream.try_clone()?;
+    let reader_writer = writer.clone();
+    let reader = thread::spawn(move || {
+        if let Err(e) = read_loop(reader_stream, reader_writer, layout) {
+            println!("\rConnection error: {}", e);
+        }
+        println!("\rServer closed the connection.");
+        process::exit(0);
+    });
 
     /*
     // Automated play to test server-client interaction.
@@ -17,32 +241,32 @@ fn main() -> Result<(), Error> {
             let cmd = format!("CELL:[{},{}]\n", x,y);
             writer.write(&(cmd.clone().into_bytes()))?;
             writer.flush()?;
-            let mut buff = String::new();
-            reader.read_line(&mut buff)?;
-        
-            println!("Server: {}", buff.trim());
         }
     }
     */
 
+    print!("> ");
+    io::stdout().flush()?;
     loop {
-        print!("> ");
-        io::stdout().flush()?;
         let mut inpt = String::new();
-        io::stdin().read_line(&mut inpt).expect("reading from stdin failed");
+        if io::stdin().read_line(&mut inpt).expect("reading from stdin failed") == 0 {
+            break
+        }
         let msg = inpt.trim().to_string();
         if msg == ":q" {break}
-        let mut m = msg.clone().into_bytes();
-        m.push(0xA);
-        writer.write(&m)?;
-        writer.flush()?;
-
-        if msg == "QUIT" {break}
+        if msg == "PUZZLE" || msg.starts_with("PUZZLE ") {
+            play_puzzle(msg.split_whitespace().nth(1).unwrap_or("medium"))?;
+            print!("> ");
+            io::stdout().flush()?;
+            continue
+        }
+        send(&writer, &msg)?;
 
-        let mut buff = String::new();
-        reader.read_line(&mut buff)?;
-        
-        println!("Server: {}", buff.trim());
+        if msg == "QUIT" {
+            // Let the reader print what is left until the server hangs up.
+            reader.join().ok();
+            break
+        }
     }
 
     Ok(())
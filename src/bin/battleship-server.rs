@@ -1,89 +1,181 @@
-use std::net::{TcpListener, TcpStream, Shutdown};
-use std::io::{BufRead, Write, BufReader, BufWriter, Error};
+use std::env;
+use std::fs;
+use std::net::TcpListener;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::thread;
-use libbattleship::{Board, CommandResult, command_handler, command_parser};
+use std::time::Duration;
+use libbattleship::accounts::AccountStore;
+#[cfg(unix)]
+use libbattleship::admin::{self, Admin};
+use libbattleship::api;
+use libbattleship::config::Config;
+use libbattleship::leaderboard::Leaderboard;
+use libbattleship::log_event;
+use libbattleship::logging;
+use libbattleship::metrics;
+use libbattleship::server::{self, Lobby, SharedLobby};
+use libbattleship::snapshot;
+use libbattleship::stats::StatsStore;
+use libbattleship::tls;
+use libbattleship::websocket;
 
 
-fn connection_handler(stream: TcpStream) -> Result<(), Error> {
-    println!("New client {}", stream.peer_addr()?);
-    let mut reader = BufReader::new(&stream);
-    let mut writer = BufWriter::new(&stream);
-    let mut board: Option<Board> = None;
+// Where accounts, game statistics and the ladder are kept unless the command line says otherwise.
+const STATS_FILE: &str = "battleship-stats.jsonl";
+const LEADERBOARD_FILE: &str = "battleship-leaderboard.json";
+const ACCOUNTS_FILE: &str = "battleship-accounts.json";
+const GAMES_FILE: &str = "battleship-games.json";
 
-    loop {
-        let mut response = String::new();
-        let bytes_read = reader.read_line(&mut response)?;
-        if bytes_read == 0 {
-            println!("Client {} disconnected.", stream.peer_addr()?);
-            return Ok(())
-        }
-        let resp = response.trim();
-        println!("{}: {}", stream.peer_addr()?, resp);
-
-        let cmd = command_parser(resp);
-        println!("Received command: {:?}", cmd);
-        let result = command_handler(&mut board, cmd);
 
-        match result {
-            CommandResult::Success(msg) => {
-                writer.write(String::as_bytes(&format!("{}\n",msg)))?;
-                writer.flush()?;
-                println!("SUCCESS: {}", msg);
-                },
-            CommandResult::Failure(msg) => {
-                writer.write(String::as_bytes(&format!("{}\n",msg)))?;
-                writer.flush()?;
-                println!("FAILURE: {}", msg);
-                },
-            CommandResult::Message(msg) => {
-                writer.write(String::as_bytes(&format!("{}\n",msg)))?;
-                writer.flush()?;
-                println!("{}", msg);
-                },
-            CommandResult::Some(b) => {
-                writer.write(b"Starting new game.\n")?;
-                writer.flush()?;
-                board = Some(b); println!("Creating new board");
-                },
-            CommandResult::None => {
-                writer.write(b"Nothing to do\n")?;
-                writer.flush()?;
-                println!("Nothing to do");
-                },
-            CommandResult::GameComplete(score) => {
-                writer.write(b"Game successcully completed.\n")?;
-                writer.flush()?;
-                println!("Game successcully completed. Score {}",score);
-                stream.shutdown(Shutdown::Both).expect("shutdown call failed");
-                return Ok(());
-            }
-            CommandResult::Quit => {
-                println!("Client quit. Closing connection.");
-                stream.shutdown(Shutdown::Both).expect("shutdown call failed");
-                return Ok(());
-            },
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1);
+}
 
-        }
 
+// Value following `flag` on the command line, if the flag is there.
+fn option<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    let i = args.iter().position(|a| a == flag)?;
+    match args.get(i+1) {
+        Some(value) => Some(value),
+        None => fail(&format!("{} needs a value", flag))
     }
-    
+}
+
+
+// SIGINT and SIGTERM save the games and stop the server, as SHUTDOWN does on the admin console.
+#[cfg(unix)]
+fn handle_signals(lobby: SharedLobby) {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    let mut signals = match signal_hook::iterator::Signals::new([SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(e) => fail(&format!("Cannot handle signals: {}", e))
+    };
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            log_event!(Info, "signal", signal = signal);
+            server::shutdown(&lobby, Duration::ZERO);
+            log_event!(Info, "shutdown");
+            process::exit(0);
+        }
+    });
 }
 
 
 fn main() -> Result<(), Error> {
-    let listener = TcpListener::bind("0.0.0.0:8888").unwrap();
+    let lobby = Lobby::shared();
+    let args: Vec<String> = env::args().collect();
+    let config_file = option(&args, "--config");
+    if let Some(path) = config_file {
+        match Config::load(Path::new(path)) {
+            Ok(config) => lobby.lock().unwrap().apply_config(&config),
+            Err(e) => fail(&format!("Cannot read the config: {}", e))
+        }
+    }
+    if let Some(level) = option(&args, "--log-level") {
+        match level.parse() {
+            Ok(level) => logging::set_level(level),
+            Err(_) => fail("--log-level is one of error, warn, info or debug")
+        }
+    }
+    if let Some(limit) = option(&args, "--hints") {
+        match limit.parse() {
+            Ok(limit) => lobby.lock().unwrap().set_hint_limit(limit),
+            Err(_) => fail("--hints needs a number")
+        }
+    }
+    let accounts_file = option(&args, "--accounts").unwrap_or(ACCOUNTS_FILE);
+    match AccountStore::open(Path::new(accounts_file)) {
+        Ok(accounts) => lobby.lock().unwrap().set_accounts(accounts),
+        Err(e) => fail(&format!("Cannot read accounts: {}", e))
+    }
+    let stats_file = option(&args, "--stats").unwrap_or(STATS_FILE);
+    match StatsStore::open(Path::new(stats_file)) {
+        Ok(stats) => lobby.lock().unwrap().set_stats(stats),
+        Err(e) => fail(&format!("Cannot read statistics: {}", e))
+    }
+    let leaderboard_file = option(&args, "--leaderboard").unwrap_or(LEADERBOARD_FILE);
+    let leaderboard = match Leaderboard::open(Path::new(leaderboard_file)) {
+        Ok(leaderboard) => leaderboard,
+        Err(e) => fail(&format!("Cannot read the leaderboard: {}", e))
+    };
+    if let Some(csv) = option(&args, "--export-leaderboard") {
+        // Export and exit, without starting the server.
+        fs::write(csv, leaderboard.to_csv())?;
+        return Ok(())
+    }
+    lobby.lock().unwrap().set_leaderboard(leaderboard);
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                thread::spawn(move || -> Result<(), Error> {
-                    connection_handler(stream)?;
-                    Ok(())
-            });
-            }
-            Err(e) => { println!("Error: {}", e); }
+    // Games saved when the server last stopped are picked up again, for their players to RESUME.
+    let games_file = Path::new(option(&args, "--games").unwrap_or(GAMES_FILE));
+    match snapshot::load(games_file) {
+        Ok(Some(saved)) => {
+            let restored = lobby.lock().unwrap().restore_games(saved);
+            log_event!(Info, "games_restored", count = restored, path = games_file.display());
         }
+        Ok(None) => {}
+        Err(e) => fail(&format!("Cannot read saved games: {}", e))
+    }
+    lobby.lock().unwrap().set_snapshot(games_file);
+    {
+        let lobby = lobby.clone();
+        thread::spawn(move || server::reap(lobby));
+    }
+    #[cfg(unix)]
+    handle_signals(lobby.clone());
+
+    let tls = match (option(&args, "--tls-cert"), option(&args, "--tls-key")) {
+        (Some(cert), Some(key)) => match tls::server_config(Path::new(cert), Path::new(key)) {
+            Ok(config) => Some(config),
+            Err(e) => fail(&format!("Cannot set up TLS: {}", e))
+        },
+        (None, None) => None,
+        _ => fail("--tls-cert and --tls-key go together")
+    };
+
+    if let Some(port) = option(&args, "--websocket") {
+        // Browsers get their own port, on the same lobby.
+        let listener = match TcpListener::bind(format!("0.0.0.0:{}", port)) {
+            Ok(listener) => listener,
+            Err(e) => fail(&format!("Cannot listen for WebSockets on port {}: {}", port, e))
+        };
+        let lobby = lobby.clone();
+        thread::spawn(move || websocket::serve(listener, lobby));
+    }
+    if let Some(port) = option(&args, "--api") {
+        let listener = match TcpListener::bind(format!("0.0.0.0:{}", port)) {
+            Ok(listener) => listener,
+            Err(e) => fail(&format!("Cannot listen for HTTP on port {}: {}", port, e))
+        };
+        let lobby = lobby.clone();
+        thread::spawn(move || api::serve(listener, lobby));
     }
 
+    if let Some(port) = option(&args, "--metrics") {
+        // Only reachable from this machine.
+        let listener = match TcpListener::bind(format!("127.0.0.1:{}", port)) {
+            Ok(listener) => listener,
+            Err(e) => fail(&format!("Cannot serve metrics on port {}: {}", port, e))
+        };
+        let lobby = lobby.clone();
+        thread::spawn(move || metrics::serve(listener, lobby));
+    }
+    #[cfg(unix)]
+    if let Some(path) = option(&args, "--admin") {
+        let listener = match admin::bind(Path::new(path)) {
+            Ok(listener) => listener,
+            Err(e) => fail(&format!("Cannot open the admin socket {}: {}", path, e))
+        };
+        let admin = Admin::new(lobby.clone(), config_file.map(PathBuf::from));
+        thread::spawn(move || admin::serve(listener, admin));
+    }
+
+    let listener = TcpListener::bind("0.0.0.0:8888").unwrap();
+    match tls {
+        Some(config) => server::serve_tls(listener, lobby, config),
+        None => server::serve(listener, lobby)
+    }
     Ok(())
 }
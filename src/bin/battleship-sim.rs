@@ -0,0 +1,110 @@
+use std::env;
+use std::process;
+use libbattleship::placement::PlacementPolicy;
+use libbattleship::simulate::{simulate, SimConfig};
+use libbattleship::strategy::{strategy_by_name, STRATEGY_NAMES};
+use libbattleship::tournament::{Format, Tournament, TournamentConfig};
+
+
+const USAGE: &str = "Usage: battleship-sim [--strategy NAME] [--games N] [--seed N] [--threads N] [--format json|csv]
+                     [--placement random|edge-hugging|spread-out|clustered|anti-heatmap]
+       battleship-sim tournament --players NAME,NAME,... [--matches N] [--seed N] [--swiss ROUNDS]";
+
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}\n{}\nStrategies: {}", msg, USAGE, STRATEGY_NAMES.join(", "));
+    process::exit(2);
+}
+
+
+// Engines that broke the rules, on stderr so the report itself stays clean.
+fn report_failures(failures: &[String]) {
+    for reason in failures {
+        eprintln!("Engine forfeits {}", reason);
+    }
+}
+
+
+fn number<T: std::str::FromStr>(flag: &str, value: Option<&String>) -> T {
+    match value.map(|v| v.parse()) {
+        Some(Ok(n)) => n,
+        _ => fail(&format!("{} needs a number", flag))
+    }
+}
+
+
+fn run_simulation(args: &[String]) {
+    let mut config = SimConfig::default();
+    let mut strategy = String::from("hunt-target");
+    let mut format = String::from("json");
+
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i+1);
+        match args[i].as_str() {
+            "--strategy" => strategy = value.cloned().unwrap_or_default(),
+            "--games" => config.games = number("--games", value),
+            "--seed" => config.seed = number("--seed", value),
+            "--threads" => config.threads = number("--threads", value),
+            "--format" => format = value.cloned().unwrap_or_default(),
+            "--placement" => config.placement = match value.map(|v| v.parse::<PlacementPolicy>()) {
+                Some(Ok(policy)) => policy,
+                _ => fail("--placement needs a placement policy")
+            },
+            other => fail(&format!("Unknown option {}", other))
+        }
+        i += 2;
+    }
+
+    if let Err(msg) = strategy_by_name(&strategy) {
+        fail(&msg);
+    }
+    let report = simulate(|| strategy_by_name(&strategy).unwrap(), &config);
+    report_failures(&report.failures);
+    match format.as_str() {
+        "json" => println!("{}", report.to_json()),
+        "csv" => print!("{}", report.to_csv()),
+        _ => fail("--format must be json or csv")
+    }
+}
+
+
+fn run_tournament(args: &[String]) {
+    let mut config = TournamentConfig::default();
+    let mut players: Vec<String> = Vec::new();
+
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i+1);
+        match args[i].as_str() {
+            "--players" => players = value.map(|v| v.split(',').map(String::from).collect()).unwrap_or_default(),
+            "--matches" => config.matches = number("--matches", value),
+            "--seed" => config.seed = number("--seed", value),
+            "--swiss" => config.format = Format::Swiss(number("--swiss", value)),
+            other => fail(&format!("Unknown option {}", other))
+        }
+        i += 2;
+    }
+
+    let report = Tournament::new(players, strategy_by_name, config).and_then(|t| t.run());
+    match report {
+        Ok(report) => {
+            report_failures(&report.failures);
+            print!("{}", report)
+        }
+        Err(msg) => fail(&msg)
+    }
+}
+
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{}\nStrategies: {}", USAGE, STRATEGY_NAMES.join(", "));
+        return
+    }
+    match args.first().map(String::as_str) {
+        Some("tournament") => run_tournament(&args[1..]),
+        _ => run_simulation(&args)
+    }
+}
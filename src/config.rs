@@ -0,0 +1,96 @@
+// Server settings that can be changed while it runs, read from a file of
+// `key = value` lines (`#` starts a comment):
+//
+//   hints = 3
+//   log_level = info    # error, warn, info or debug
+//
+// and the limits on abusive clients (see limits.rs), given here with their defaults:
+//
+//   max_connections_per_ip = 8
+//   commands_per_second = 10       # per connection, after
+//   command_burst = 20             # commands in a row
+//   max_line_length = 1024         # bytes
+//   invalid_commands_per_minute = 20
+//   ban_seconds = 300              # for going over invalid_commands_per_minute
+//
+// The admin console's RELOAD reads the file again and applies it to the lobby.
+
+use std::fs;
+use std::path::Path;
+
+use crate::limits::Limits;
+use crate::logging::Level;
+use crate::server::DEFAULT_HINT_LIMIT;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub hints: u32,
+    pub log_level: Level,
+    pub limits: Limits,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config{hints: DEFAULT_HINT_LIMIT, log_level: Level::Info, limits: Limits::default()}
+    }
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(format!("line {}: expected key = value", i+1))
+            };
+            let number = || value.parse::<u32>().map_err(|_| format!("line {}: {} needs a number", i+1, key));
+            let rate = || match value.parse::<f64>() {
+                Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+                _ => Err(format!("line {}: {} needs a positive number", i+1, key))
+            };
+            let limits = &mut config.limits;
+            match key {
+                "hints" => config.hints = number()?,
+                "max_connections_per_ip" => limits.max_connections_per_ip = number()? as usize,
+                "commands_per_second" => limits.commands_per_second = rate()?,
+                "command_burst" => limits.command_burst = rate()?,
+                "max_line_length" => limits.max_line_length = number()? as usize,
+                "invalid_commands_per_minute" => limits.invalid_commands_per_minute = rate()?,
+                "ban_seconds" => limits.ban_seconds = number()? as u64,
+                "log_level" => {
+                    config.log_level = value.parse().map_err(|_| format!("line {}: unknown log level {}", i+1, value))?
+                }
+                _ => return Err(format!("line {}: unknown setting {}", i+1, key))
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Config::parse(&text).map_err(|e| format!("{} {}", path.display(), e))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_are_read() {
+        assert_eq!(Config::parse("# defaults\n\n").unwrap(), Config::default());
+        assert_eq!(Config::parse("hints = 7  # generous\nlog_level = DEBUG").unwrap(),
+                   Config{hints: 7, log_level: Level::Debug, ..Config::default()});
+        assert_eq!(Config::parse("commands_per_second = 2.5\nban_seconds = 60").unwrap().limits,
+                   Limits{commands_per_second: 2.5, ban_seconds: 60, ..Limits::default()});
+        assert_eq!(Config::parse("command_burst = 0").unwrap_err(), "line 1: command_burst needs a positive number");
+        assert_eq!(Config::parse("hints = many").unwrap_err(), "line 1: hints needs a number");
+        assert_eq!(Config::parse("\ncolour = red").unwrap_err(), "line 2: unknown setting colour");
+    }
+}
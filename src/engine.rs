@@ -0,0 +1,282 @@
+// Third party bots, run as a subprocess and spoken to over stdin/stdout,
+// one line per message (in the spirit of UCI for chess engines).
+//
+// Runner to bot                        Bot to runner
+// ---------------------------------    ---------------------------------
+// battleship 1                         ready [name]
+// newgame 9 9 C5:5 H4:4 L3:3 A2:2      (nothing; board columns, rows, fleet)
+// place                                auto
+//                                      or: ship C5 A1 Right ... then done
+// shoot                                fire C4
+// result C4 hit|miss|sunk <ship>       (nothing)
+// quit                                 (exit)
+//
+// Lines from the bot starting with "info " are ignored, so bots can log.
+// A bot that does not answer within the timeout, fires at a cell it has
+// already tried or off the board, or sends anything unexpected forfeits
+// the game.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use strum::IntoEnumIterator;
+
+use crate::{BoardConfig, Position, ShipType, ShotOutcome};
+use crate::layout::parse_layout;
+use crate::strategy::{CellState, FogGrid, Strategy};
+
+
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+pub struct EngineStrategy {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+    timeout: Duration,
+    failure: Option<String>,
+}
+
+impl EngineStrategy {
+    pub fn spawn(command: &str) -> Result<EngineStrategy, String> {
+        Self::spawn_with_timeout(command, DEFAULT_TIMEOUT)
+    }
+
+    // Starts the bot and waits for it to answer the handshake.
+    pub fn spawn_with_timeout(command: &str, timeout: Duration) -> Result<EngineStrategy, String> {
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or("Empty engine command")?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Cannot start {}: {}", program, e))?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        let (tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) => if tx.send(line).is_err() {break},
+                    Err(_) => break
+                }
+            }
+        });
+
+        let mut engine = EngineStrategy{name: command.to_string(), child, stdin, lines, timeout, failure: None};
+        engine.send(&format!("battleship {}", PROTOCOL_VERSION))?;
+        let reply = engine.receive()?;
+        let mut words = reply.splitn(2, ' ');
+        if words.next() != Some("ready") {
+            return Err(format!("{}: expected ready, got {:?}", command, reply))
+        }
+        if let Some(name) = words.next().map(str::trim).filter(|n| !n.is_empty()) {
+            engine.name = name.to_string();
+        }
+
+        let fleet: Vec<String> = ShipType::iter().map(|s| format!("{:?}:{}", s, s.get_size())).collect();
+        engine.send(&format!("newgame 9 9 {}", fleet.join(" ")))?;
+        Ok(engine)
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{}", line)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| format!("{}: {}", self.name, e))
+    }
+
+    fn receive(&mut self) -> Result<String, String> {
+        loop {
+            let line = match self.lines.recv_timeout(self.timeout) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => return Err(format!("{}: timed out", self.name)),
+                Err(RecvTimeoutError::Disconnected) => return Err(format!("{}: exited", self.name))
+            };
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with("info ") {
+                return Ok(line.to_string())
+            }
+        }
+    }
+
+    // Records the first thing that went wrong; the bot loses the game.
+    fn fail(&mut self, reason: String) {
+        if self.failure.is_none() {
+            self.failure = Some(reason);
+        }
+    }
+
+    fn request_shot(&mut self, fog: &FogGrid) -> Result<Position, String> {
+        self.send("shoot")?;
+        let reply = self.receive()?;
+        let coord = reply.strip_prefix("fire ")
+            .ok_or_else(|| format!("{}: expected fire, got {:?}", self.name, reply))?;
+        let pos = Position::from_coord(coord.trim())
+            .ok_or_else(|| format!("{}: invalid cell {:?}", self.name, coord))?;
+        if fog.get(&pos) != CellState::Unknown {
+            return Err(format!("{}: fired at {} again", self.name, pos))
+        }
+        Ok(pos)
+    }
+
+    fn request_placement(&mut self) -> Result<BoardConfig, String> {
+        self.send("place")?;
+        let mut layout = String::new();
+        loop {
+            let reply = self.receive()?;
+            match reply.as_str() {
+                "auto" if layout.is_empty() => return Ok(BoardConfig::Auto),
+                "done" => break,
+                _ => match reply.strip_prefix("ship ") {
+                    Some(ship) => {
+                        layout.push_str(ship);
+                        layout.push('\n');
+                    }
+                    None => return Err(format!("{}: expected ship or done, got {:?}", self.name, reply))
+                }
+            }
+        }
+        parse_layout(&layout).map_err(|e| format!("{}: illegal fleet: {}", self.name, e))
+    }
+}
+
+impl Strategy for EngineStrategy {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn next_shot(&mut self, fog: &FogGrid, rng: &mut StdRng) -> Position {
+        if self.failure.is_none() {
+            match self.request_shot(fog) {
+                Ok(pos) => return pos,
+                Err(reason) => self.fail(reason)
+            }
+        }
+        Position::new(0,0)
+    }
+
+    fn observe(&mut self, pos: Position, outcome: ShotOutcome) {
+        if self.failure.is_some() {
+            return
+        }
+        let result = match outcome {
+            ShotOutcome::Miss => String::from("miss"),
+            ShotOutcome::Hit => String::from("hit"),
+            ShotOutcome::Sunk(shiptype) => format!("sunk {:?}", shiptype)
+        };
+        if let Err(reason) = self.send(&format!("result {} {}", pos, result)) {
+            self.fail(reason);
+        }
+    }
+
+    fn placement(&mut self, rng: &mut StdRng) -> BoardConfig {
+        match self.request_placement() {
+            Ok(config) => config,
+            Err(reason) => {
+                self.fail(reason);
+                BoardConfig::Manual(Default::default())
+            }
+        }
+    }
+
+    fn get_failure(&self) -> Option<&str> {
+        self.failure.as_deref()
+    }
+}
+
+impl Drop for EngineStrategy {
+    fn drop(&mut self) {
+        self.send("quit").ok();
+        // Give the bot a moment to exit by itself before it is killed.
+        for _ in 0..20 {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::placement::PlacementPolicy;
+    use crate::simulate::play_game;
+
+    // Fires along the board from A1, one cell after the other.
+    const SWEEP_BOT: &str = r#"n=0
+while read cmd args; do
+  case $cmd in
+    battleship) echo "ready sweeper" ;;
+    place) printf 'ship C5 A1 Right\nship H4 A2 Right\nship L3 A3 Right\nship A2 A4 Right\ndone\n' ;;
+    shoot) echo "fire $(echo ABCDEFGHI | cut -c$((n % 9 + 1)))$((n / 9 + 1))"; n=$((n + 1)) ;;
+    quit) exit 0 ;;
+  esac
+done"#;
+
+    // A temporary script file, deleted when the test is done with it.
+    struct Script(PathBuf);
+
+    impl Drop for Script {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    // Writes a shell script bot to a temporary file and starts it.
+    // Keep the Script for as long as the bot runs.
+    fn bot(script: &str, timeout: Duration) -> (Script, Result<EngineStrategy, String>) {
+        static SCRIPTS: AtomicUsize = AtomicUsize::new(0);
+        let n = SCRIPTS.fetch_add(1, Ordering::SeqCst);
+        let path = std::env::temp_dir().join(format!("battleship-bot-{}-{}.sh", std::process::id(), n));
+        std::fs::write(&path, script).unwrap();
+        let engine = EngineStrategy::spawn_with_timeout(&format!("sh {}", path.display()), timeout);
+        (Script(path), engine)
+    }
+
+    #[test]
+    fn engine_plays_a_game() {
+        let (_script, engine) = bot(SWEEP_BOT, Duration::from_secs(5));
+        let mut engine = engine.unwrap();
+        assert_eq!(engine.name(), "sweeper");
+        let mut rng = StdRng::seed_from_u64(1);
+        let layout = engine.placement(&mut rng);
+        assert!(matches!(layout, BoardConfig::Manual(ref l) if l.len() == 4));
+        let record = play_game(&mut engine, PlacementPolicy::Random, &mut rng);
+        assert!(!engine.has_forfeited());
+        assert!(record.shots <= 81);
+    }
+
+    #[test]
+    fn illegal_moves_forfeit() {
+        let script = "while read cmd args; do case $cmd in battleship) echo ready;; shoot) echo fire A1;; esac; done";
+        let (_script, engine) = bot(script, Duration::from_secs(5));
+        let mut engine = engine.unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut fog = FogGrid::new();
+        assert_eq!(engine.next_shot(&fog, &mut rng), Position::new(1,1));
+        fog.record(Position::new(1,1), ShotOutcome::Miss);
+        engine.next_shot(&fog, &mut rng);
+        assert!(engine.get_failure().unwrap().contains("again"));
+    }
+
+    #[test]
+    fn silent_engines_time_out() {
+        let (_script, result) = bot("while read line; do :; done", Duration::from_millis(100));
+        assert!(result.err().unwrap().contains("timed out"));
+    }
+}
@@ -0,0 +1,107 @@
+// Plain text fleet layouts, so favourite setups can be saved and shared.
+//
+// One ship per line: ship type, start cell and direction, the same
+// arguments the PLACE command takes. Blank lines and anything after a '#'
+// are ignored.
+//
+//   # Corner fleet
+//   C5 A1 Right
+//   H4 I6 Down
+//   L3 A9 Right
+//   A2 E5 Up
+
+use std::collections::HashMap;
+use strum::IntoEnumIterator;
+
+use crate::{Board, BoardConfig, Direction, DirectionName, Position, ShipType};
+
+
+// A whole fleet: every ship must be there.
+pub fn parse_layout(text: &str) -> Result<BoardConfig, String> {
+    let layout = parse_ships(text)?;
+    let missing: Vec<String> = ShipType::iter()
+        .filter(|shiptype| !layout.contains_key(shiptype))
+        .map(|shiptype| format!("{:?}", shiptype))
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("Fleet incomplete, missing {}", missing.join(" ")))
+    }
+    Ok(BoardConfig::Manual(layout))
+}
+
+// The ships placed so far, as when a saved game is restored in the middle of setting up.
+pub fn parse_partial_layout(text: &str) -> Result<BoardConfig, String> {
+    parse_ships(text).map(BoardConfig::Manual)
+}
+
+fn parse_ships(text: &str) -> Result<HashMap<ShipType, (Position, Direction)>, String> {
+    let mut layout: HashMap<ShipType, (Position, Direction)> = HashMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 3 {
+            return Err(format!("Line {}: expected <ship> <cell> <direction>", n+1))
+        }
+        let shiptype: ShipType = fields[0].parse()
+            .map_err(|_| format!("Line {}: unknown ship {}", n+1, fields[0]))?;
+        let pos = Position::from_coord(fields[1])
+            .ok_or_else(|| format!("Line {}: invalid cell {}", n+1, fields[1]))?;
+        let dir: DirectionName = fields[2].parse()
+            .map_err(|_| format!("Line {}: unknown direction {}", n+1, fields[2]))?;
+        if layout.insert(shiptype, (pos, Direction::new(dir))).is_some() {
+            return Err(format!("Line {}: {:?} placed twice", n+1, shiptype))
+        }
+    }
+    if !BoardConfig::validate_manual(&layout) {
+        return Err(String::from("Ships overlap or fall outside the board"))
+    }
+    Ok(layout)
+}
+
+
+// One line per ship, in fleet order.
+pub fn layout_lines(layout: &HashMap<ShipType, (Position, Direction)>) -> Vec<String> {
+    ShipType::iter()
+        .filter_map(|shiptype| {
+            layout.get(&shiptype)
+                .map(|(pos, dir)| format!("{:?} {} {:?}", shiptype, pos, dir.get_name()))
+        })
+        .collect()
+}
+
+
+pub fn serialize_layout(board: &Board) -> String {
+    let mut text = layout_lines(&board.get_layout()).join("\n");
+    text.push('\n');
+    text
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_round_trip() {
+        let text = "# Corner fleet\nC5 A1 Right\n\nh4 i6 down  # along the edge\nL3 A9 Right\nA2 E5 Up\n";
+        let config = parse_layout(text).unwrap();
+        let mut board = Board::new();
+        board.setup(config).unwrap();
+        assert_eq!(serialize_layout(&board), "C5 A1 Right\nH4 I6 Down\nL3 A9 Right\nA2 E5 Up\n");
+    }
+
+    #[test]
+    fn bad_layouts_are_rejected() {
+        assert_eq!(parse_layout("C5 A1").err().unwrap(), "Line 1: expected <ship> <cell> <direction>");
+        assert_eq!(parse_layout("B7 A1 Right").err().unwrap(), "Line 1: unknown ship B7");
+        assert_eq!(parse_layout("C5 A1 Right\nC5 A2 Right").err().unwrap(), "Line 2: C5 placed twice");
+        assert!(parse_layout("C5 A1 Right\nH4 B1 Down").is_err());
+        assert!(parse_layout("C5 F1 Right").is_err());
+        assert_eq!(parse_layout("C5 A1 Right\nL3 A3 Right").err().unwrap(), "Fleet incomplete, missing H4 A2");
+        assert!(parse_partial_layout("C5 A1 Right\nL3 A3 Right").is_ok());
+        assert!(parse_partial_layout("C5 F1 Right").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+// Standing ladder of named players, rated by Elo from finished two player
+// matches. Kept in a JSON file, rewritten after every match.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::tournament::Elo;
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rating {
+    pub name: String,
+    pub elo: f64,
+    pub games: u32,
+    pub wins: u32,
+    pub losses: u32,
+}
+
+impl Rating {
+    fn new(name: &str) -> Rating {
+        Rating{name: name.to_string(), elo: Elo::INITIAL, games: 0, wins: 0, losses: 0}
+    }
+}
+
+
+pub struct Leaderboard {
+    path: Option<PathBuf>,  // None keeps the ladder in memory
+    ratings: HashMap<String, Rating>,
+}
+
+impl Leaderboard {
+    pub fn in_memory() -> Leaderboard {
+        Leaderboard{path: None, ratings: HashMap::new()}
+    }
+
+    // Loads the ladder from `path`, which is created after the first match if missing.
+    pub fn open(path: &Path) -> Result<Leaderboard, String> {
+        let ratings: Vec<Rating> = match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e))
+        };
+        let ratings = ratings.into_iter().map(|r| (r.name.clone(), r)).collect();
+        Ok(Leaderboard{path: Some(path.to_path_buf()), ratings})
+    }
+
+    // Written to a temporary file first, so a crash never leaves half a ladder behind.
+    fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(())
+        };
+        let json = serde_json::to_string_pretty(&self.ranking()).unwrap();
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, json)
+            .and_then(|_| fs::rename(&temp, path))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn record(&mut self, winner: &str, loser: &str) -> Result<(), String> {
+        if winner == loser {
+            return Ok(())
+        }
+        let w = self.ratings.get(winner).cloned().unwrap_or_else(|| Rating::new(winner));
+        let l = self.ratings.get(loser).cloned().unwrap_or_else(|| Rating::new(loser));
+        let change = Elo::K * (1.0 - Elo::expected(w.elo, l.elo));
+        self.ratings.insert(winner.to_string(), Rating{elo: w.elo + change, games: w.games + 1, wins: w.wins + 1, ..w});
+        self.ratings.insert(loser.to_string(), Rating{elo: l.elo - change, games: l.games + 1, losses: l.losses + 1, ..l});
+        self.save()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Rating> {
+        self.ratings.get(name)
+    }
+
+    // Best first.
+    pub fn ranking(&self) -> Vec<&Rating> {
+        let mut ranking: Vec<&Rating> = self.ratings.values().collect();
+        ranking.sort_by(|a, b| b.elo.partial_cmp(&a.elo).unwrap().then(a.name.cmp(&b.name)));
+        ranking
+    }
+
+    // Answer to LEADERBOARD: a header line, then one line per player.
+    pub fn report(&self, n: usize) -> String {
+        let ranking = self.ranking();
+        let mut lines = vec![format!("LEADERBOARD {}", ranking.len().min(n))];
+        for (rank, r) in ranking.iter().take(n).enumerate() {
+            lines.push(format!("{} {} {:.0} {} {} {}", rank+1, r.name, r.elo, r.games, r.wins, r.losses));
+        }
+        lines.join("\n")
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("rank,name,elo,games,wins,losses\n");
+        for (rank, r) in self.ranking().iter().enumerate() {
+            out += &format!("{},{},{:.1},{},{},{}\n", rank+1, r.name, r.elo, r.games, r.wins, r.losses);
+        }
+        out
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ladder_survives_restarts() {
+        let path = std::env::temp_dir().join(format!("battleship-leaderboard-{}.json", std::process::id()));
+        fs::remove_file(&path).ok();
+        let mut ladder = Leaderboard::open(&path).unwrap();
+        ladder.record("ann", "bob").unwrap();
+        ladder.record("ann", "cid").unwrap();
+        ladder.record("ann", "ann").unwrap();
+
+        let ladder = Leaderboard::open(&path).unwrap();
+        let ann = ladder.get("ann").unwrap();
+        assert_eq!((ann.games, ann.wins, ann.losses), (2, 2, 0));
+        assert!(ann.elo > Elo::INITIAL);
+        assert_eq!(ladder.ranking()[0].name, "ann");
+        assert_eq!(ladder.report(1), "LEADERBOARD 1\n1 ann 1516 2 2 0");
+        assert!(ladder.to_csv().starts_with("rank,name,elo,games,wins,losses\n1,ann,1515."));
+        assert_eq!(ladder.to_csv().lines().count(), 4);
+        fs::remove_file(&path).ok();
+    }
+}
@@ -2,18 +2,47 @@
 #![allow(unused_variables)]
 
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use rand::{
     distributions::{Distribution, Standard},
     Rng};
 use strum::IntoEnumIterator;
-use strum_macros::EnumIter;
-use almost;
+use strum_macros::{EnumIter, EnumString};
 use regex::Regex;
 
-
+pub mod accounts;
+#[cfg(unix)]
+pub mod admin;
+pub mod api;
+pub mod config;
+pub mod engine;
+pub mod layout;
+pub mod leaderboard;
+pub mod limits;
+pub mod logging;
+pub mod metrics;
+pub mod placement;
+pub mod puzzle;
+pub mod server;
+pub mod simulate;
+pub mod snapshot;
+pub mod solver;
+pub mod stats;
+pub mod strategy;
+pub mod tls;
+pub mod tournament;
+pub mod websocket;
+
+// One bit per cell, see Board::index.
+type Mask = u128;
+
+// The masks are the whole state of the board: Cell values are made from them when asked for.
 pub struct Board {
-    cells: HashMap<Position, Cell>,
-    ships: HashSet<Ship>,
+    ships: Vec<Ship>,
+    occupied: Mask,
+    shot: Mask,
+    ship_cells: u32,   // Number of occupied cells
+    hits_landed: u32,  // Number of occupied cells hit so far
     n_rows: u32,
     n_cols: u32,
     game_progress: f32,
@@ -22,13 +51,14 @@ pub struct Board {
 
 pub enum BoardConfig {
     Auto,
-    Manual(HashMap<ShipType, (Position, Direction)>)
+    Manual(HashMap<ShipType, (Position, Direction)>),
+    Policy(placement::PlacementPolicy)
 }
 
 impl BoardConfig {
     pub fn validate(&self) -> bool {
         match self {
-            Self::Auto => true,
+            Self::Auto | Self::Policy(_) => true,
             Self::Manual(manualconf) => Self::validate_manual(manualconf)
         }
     }
@@ -37,9 +67,9 @@ impl BoardConfig {
         let mut board = Board::new();  // Make a temporary board
         let mut occupied_cells = HashSet::<Position>::new();
         for shiptype in ShipType::iter() {
-            let mut ship = Ship::new(shiptype);
+            let ship = Ship::new(shiptype);
             let ship_pos = boardconf.get(&shiptype);
-            if let None = ship_pos {
+            if ship_pos.is_none() {
                 continue;
             }
             let (pos, dir) = ship_pos.unwrap();
@@ -49,44 +79,46 @@ impl BoardConfig {
             }
             let cells_taken = place_result.unwrap();
             occupied_cells.extend(cells_taken.clone());
-            ship.cells = cells_taken;
-            board.ships.insert(ship);
+            board.add_ship(shiptype, cells_taken);
         }
         true
     }
 }
 
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Board {
     const COLS: [char;9] = ['A','B','C','D','E','F','G','H','I'];
     const ROWS: [u8;9] = [1,2,3,4,5,6,7,8,9];
+    const ALL_CELLS: Mask = (1 << 81) - 1;
 
     pub fn new() -> Board {
         let n_rows: u32 = 9;
         let n_cols: u32 = 9;
         let game_progress: f32 = 0.0;
-        let mut cells: HashMap<Position, Cell> = HashMap::new();
 
-        for x in 1..=n_cols as i32 {
-            for y in 1..=n_rows as i32 {
-                cells.insert(Position::new(x,y), Cell::new(Position::new(x,y)));
-            }
-        }
-
-        let ships: HashSet<Ship> = HashSet::new();
-
-        Board{cells:cells, ships:ships, game_complete:false, n_rows, n_cols, game_progress}
+        Board{ships: Vec::new(), occupied: 0, shot: 0, ship_cells: 0, hits_landed: 0,
+              game_complete: false, n_rows, n_cols, game_progress}
     }
 
     pub fn setup(&mut self, config:BoardConfig) -> Result<(), &str> {
+        self.setup_with_rng(config, &mut rand::thread_rng())
+    }
+
+    // Same as setup, with random placement drawn from `rng` so games can be replayed from a seed.
+    #[allow(clippy::needless_return)]
+    pub fn setup_with_rng<R: Rng>(&mut self, config:BoardConfig, rng: &mut R) -> Result<(), &str> {
+        if let BoardConfig::Policy(policy) = config {
+            let layout = policy.choose_layout(rng);
+            return self.setup_with_rng(BoardConfig::Manual(layout), rng)
+        }
         if let BoardConfig::Manual(ship_positions) = config {
             for (shiptype, (start_pos, dir)) in ship_positions {
-                let mut ship = Ship::new(shiptype);
-                let place_result = self.place_ship_manual(&ship, &start_pos, &dir);
-                if let Ok(cells_taken) = place_result {
-                    ship.cells = cells_taken;
-                    self.ships.insert(ship);
-                }
-                else {
+                if self.place_ship(shiptype, &start_pos, &dir).is_err() {
                     return Err("Invalid Position for Ship")
                 }
             }
@@ -94,112 +126,142 @@ impl Board {
         }
 
         else {
-            for shiptype in ShipType::iter() {
-                let mut ship = Ship::new(shiptype);
-                let cells_taken = self.place_ship_auto(&ship);
-                ship.cells = cells_taken;
-                self.ships.insert(ship);
+            // Ships already on the board stay where they are.
+            for shiptype in self.get_unplaced_ships() {
+                let ship = Ship::new(shiptype);
+                let cells_taken = self.place_ship_auto_with_rng(&ship, rng);
+                if cells_taken.is_empty() {
+                    return Err("No room for the fleet")
+                }
+                self.add_ship(shiptype, cells_taken);
             }
             return Ok(())
         }
     }
 
-    pub fn contains_cell(&self, pos: &Position) -> bool {
-        match self.cells.get(pos) {
-            Some(_cell) => true,
-            None => false
+    // Cells are stored row by row, so A1 is bit 0, B1 bit 1, ... and I9 bit 80.
+    fn index(&self, pos: &Position) -> Option<usize> {
+        if self.is_valid_position(pos) {
+            Some(((pos.y - 1) * self.n_cols as i32 + pos.x - 1) as usize)
+        }
+        else {
+            None
         }
     }
 
-    pub fn get_next_pos(&self, pos:Position, dir: Direction) -> Position {
-        let p = Position{
-            x: pos.x + dir.x as i32,
-            y: pos.y + dir.y as i32
-        };
-        p
+    fn position_at(&self, index: usize) -> Position {
+        let n_cols = self.n_cols as usize;
+        Position::new((index % n_cols) as i32 + 1, (index / n_cols) as i32 + 1)
     }
 
-    pub fn get_next_cell(&self, pos:Position, dir: Direction) -> Option<(Position, &Cell)> {
-        let p = Position{
-            x: pos.x + dir.x as i32,
-            y: pos.y + dir.y as i32
-        };
+    fn positions(&self, mut mask: Mask) -> Vec<Position> {
+        let mut positions = Vec::with_capacity(mask.count_ones() as usize);
+        while mask != 0 {
+            positions.push(self.position_at(mask.trailing_zeros() as usize));
+            mask &= mask - 1;
+        }
+        positions
+    }
+
+    fn cell(&self, pos: &Position) -> Option<Cell> {
+        self.index(pos).map(|i| Cell{
+            position: *pos,
+            occupied: self.occupied & (1 << i) != 0,
+            hitcount: (self.shot >> i & 1) as usize,  // Repeat shots are not told apart
+        })
+    }
+
+    fn set_occupied(&mut self, pos: &Position) {
+        if let Some(i) = self.index(pos) {
+            if self.occupied & (1 << i) == 0 {
+                self.occupied |= 1 << i;
+                self.ship_cells += 1;
+                // A ship put down where a shot already landed starts out hit.
+                if self.shot & (1 << i) != 0 {
+                    self.hits_landed += 1;
+                }
+            }
+            self.update_status();
+        }
+    }
+
+    fn is_occupied(&self, pos: &Position) -> bool {
+        self.index(pos).is_some_and(|i| self.occupied & (1 << i) != 0)
+    }
 
-        match self.cells.get(&p) {
-            Some(cell) => Some((p, cell)),
-            None => None
+    fn add_ship(&mut self, shiptype: ShipType, cells: Vec<Position>) {
+        let mut ship = Ship::new(shiptype);
+        ship.mask = cells.iter().filter_map(|pos| self.index(pos)).fold(0, |mask, i| mask | (1 << i));
+        ship.cells = cells;
+        self.ships.push(ship);
+    }
+
+    pub fn contains_cell(&self, pos: &Position) -> bool {
+        self.index(pos).is_some()
+    }
+
+    pub fn get_next_pos(&self, pos:Position, dir: Direction) -> Position {
+        Position{
+            x: pos.x.saturating_add(dir.x as i32),
+            y: pos.y.saturating_add(dir.y as i32)
         }
     }
 
+    // The cell is made up from the masks, so it comes by value rather than by reference.
+    pub fn get_next_cell(&self, pos:Position, dir: Direction) -> Option<(Position, Cell)> {
+        let p = self.get_next_pos(pos, dir);
+        self.cell(&p).map(|cell| (p, cell))
+    }
+
     pub fn is_valid_position(&self, pos: &Position) -> bool {
         ((pos.x > 0) & (pos.x < (self.n_cols as i32+1))) & ((pos.y > 0) & (pos.y < (self.n_rows as i32+1)))
     }
 
     pub fn get_occupied_cells(&self) -> HashSet<Position> {
-        let mut occupied_cells: HashSet<Position> = HashSet::new();
-
-        for (pos,cell) in self.cells.iter() {
-            if cell.is_occupied() {
-                occupied_cells.insert(pos.clone());
-            }
-        }
-        occupied_cells
+        self.positions(self.occupied).into_iter().collect()
     }
 
     pub fn get_unoccupied_cells(&self) -> HashSet<Position> {
-        let occupied_cells = self.get_occupied_cells();
-        let mut unoccupied_cells: HashSet<Position> = HashSet::new();
-
-        // First add all cells
-        for pos in self.cells.keys() {
-            unoccupied_cells.insert(pos.clone());
-        }
-        // Now remove occupied cells
-        for pos in occupied_cells {
-            unoccupied_cells.remove(&pos);
-        }
-        unoccupied_cells
+        self.positions(Self::ALL_CELLS & !self.occupied).into_iter().collect()
     }
 
     pub fn place_ship_auto(&mut self, ship:&Ship) -> Vec<Position> {
-        // In Progress
+        self.place_ship_auto_with_rng(ship, &mut rand::thread_rng())
+    }
+
+    // Returns no cells at all if the ship fits nowhere.
+    pub fn place_ship_auto_with_rng<R: Rng>(&mut self, ship:&Ship, rng: &mut R) -> Vec<Position> {
         let cells_needed = ship.ship_type.get_size() as usize;
-        let unoccupied_cells = self.get_unoccupied_cells();
+        let unoccupied_cells = self.positions(Self::ALL_CELLS & !self.occupied);
+        if !solver::placements(cells_needed as i32).iter().any(|mask| mask & self.occupied == 0) {
+            return Vec::new()
+        }
         let mut cells_taken: Vec<Position> = Vec::new();
         let mut ship_placed = false;
 
         while ! ship_placed {
             cells_taken.clear();  // Reset cells taken
             // Pick a random start position
-            let i = rand::thread_rng().gen_range(0..unoccupied_cells.len());
-            let start_pos = unoccupied_cells.iter().nth(i).unwrap().clone();
-            let start_cell = self.cells.get(&start_pos).unwrap();
-            if start_cell.is_occupied() {
-                continue  // Start again if occupied
-            }
+            let i = rng.gen_range(0..unoccupied_cells.len());
+            let start_pos = unoccupied_cells[i];
             cells_taken.push(start_pos);
             // Pick a random direction to move in
-            let dir_name: DirectionName = rand::random();
+            let dir_name: DirectionName = rng.gen();
             let dir = Direction::new(dir_name);
             // Start moving
-            let mut current_pos = start_pos.clone();
+            let mut current_pos = start_pos;
             for i in 1..cells_needed {
-                if let None = self.get_next_cell(start_pos, dir) {
-                    break;  // Fell outside the board
-                }
-                // Check if next cell is occupied
                 let next_pos = self.get_next_pos(current_pos, dir);
                 if !self.is_valid_position(&next_pos) {
                     break;  // Fell outside the board
                 }
-                let next_cell = self.cells.get(&next_pos).unwrap();
-                if next_cell.is_occupied() {
+                if self.is_occupied(&next_pos) {
                     break;  // Start again if occupied
                 }
-                current_pos = next_pos.clone();
+                current_pos = next_pos;
                 cells_taken.push(current_pos);
             }
-            
+
             if cells_taken.len() == cells_needed {
                 // Success
                 ship_placed = true;
@@ -209,97 +271,172 @@ impl Board {
         // Set of taken cells is finalized.
         // Now we can set them as occupied.
         for pos in cells_taken.iter() {
-            let cell = self.cells.get_mut(pos).unwrap();
-            cell.set_occupied();
+            self.set_occupied(pos);
         }
         // Return cells taken.
         cells_taken
     }
 
-    pub fn place_ship_manual(&mut self, ship: &Ship, start_position: &Position, direction: &Direction) -> Result<Vec<Position>, &str> {
+    pub fn place_ship_manual(&mut self, ship: &Ship, start_position: &Position, direction: &Direction) -> Result<Vec<Position>, &'static str> {
         let cells_needed = ship.ship_type.get_size() as usize;
-        let start_cell = self.cells.get(start_position).unwrap();
+        if !self.is_valid_position(start_position) {
+            return Err("Ship fell outside the board")
+        }
+        if self.is_occupied(start_position) {
+            return Err("Collision with another ship")
+        }
         let mut cells_taken: Vec<Position> = Vec::new();
-        let start_pos = start_position.clone();
-        let dir = direction.clone();
+        let start_pos = *start_position;
+        let dir = *direction;
         cells_taken.push(start_pos);
         // Start moving
-        let mut current_pos = start_pos.clone();
+        let mut current_pos = start_pos;
         for i in 1..cells_needed {
-            if let None = self.get_next_cell(start_pos, dir) {
-                return Err("Ship fell outside the board")
-            }
-            // Check if next cell is occupied
             let next_pos = self.get_next_pos(current_pos, dir);
             if !self.is_valid_position(&next_pos) {
                 return Err("Ship fell outside the board")
             }
-            let next_cell = self.cells.get(&next_pos).unwrap();
-            if next_cell.is_occupied() {
+            // Check if next cell is occupied
+            if self.is_occupied(&next_pos) {
                 return Err("Collision with another ship")
             }
-            current_pos = next_pos.clone();
+            current_pos = next_pos;
             cells_taken.push(current_pos);
         }
         // Set of taken cells is finalized.
         // Now we can set them as occupied.
         for pos in cells_taken.iter() {
-            let cell = self.cells.get_mut(pos).unwrap();
-            cell.set_occupied();
+            self.set_occupied(pos);
         }
         Ok(cells_taken)
     }
 
+    // Places a single ship, as when a player sets up their fleet one ship at a time.
+    pub fn place_ship(&mut self, shiptype: ShipType, start_position: &Position, direction: &Direction) -> Result<(), &'static str> {
+        if self.ships.iter().any(|ship| ship.ship_type == shiptype) {
+            return Err("Ship already placed")
+        }
+        let ship = Ship::new(shiptype);
+        let cells_taken = self.place_ship_manual(&ship, start_position, direction)?;
+        self.add_ship(shiptype, cells_taken);
+        Ok(())
+    }
+
+    // Randomly places whichever ships have not been placed yet.
+    pub fn place_remaining_auto(&mut self) {
+        for shiptype in self.get_unplaced_ships() {
+            let ship = Ship::new(shiptype);
+            let cells_taken = self.place_ship_auto(&ship);
+            if !cells_taken.is_empty() {
+                self.add_ship(shiptype, cells_taken);
+            }
+        }
+    }
+
+    pub fn get_unplaced_ships(&self) -> Vec<ShipType> {
+        ShipType::iter()
+            .filter(|shiptype| !self.ships.iter().any(|ship| ship.ship_type == *shiptype))
+            .collect()
+    }
+
+    // Start position and direction of every ship on the board, the same shape BoardConfig::Manual takes.
+    pub fn get_layout(&self) -> HashMap<ShipType, (Position, Direction)> {
+        let mut layout = HashMap::new();
+        for ship in self.ships.iter() {
+            let start = ship.cells[0];
+            let dir = match ship.cells.get(1) {
+                Some(next) => Direction::from_step(next.x - start.x, next.y - start.y),
+                None => Some(Direction::new(DirectionName::Right))
+            };
+            if let Some(dir) = dir {
+                layout.insert(ship.ship_type, (start, dir));
+            }
+        }
+        layout
+    }
+
+    pub fn is_ship_sunk(&self, shiptype: ShipType) -> bool {
+        self.ships.iter().any(|ship| ship.ship_type == shiptype && ship.mask & !self.shot == 0)
+    }
+
+    // Whether some ship has been hit but is still afloat.
+    pub fn has_damaged_ship(&self) -> bool {
+        self.ships.iter().any(|ship| ship.mask & self.shot != 0 && ship.mask & !self.shot != 0)
+    }
+
     pub fn get_contents(&self) -> [[char; 9]; 9] {
         let mut contents = [['0'; 9]; 9];
-        for (pos,cell) in self.cells.iter() {
-            if cell.is_occupied() {
-                contents[pos.x as usize -1][pos.y as usize -1] = '1';
+        for pos in self.positions(self.occupied) {
+            contents[pos.x as usize -1][pos.y as usize -1] = '1';
+        }
+        for pos in self.positions(self.occupied & self.shot) {
+            contents[pos.x as usize -1][pos.y as usize -1] = 'X';
+        }
+        contents
+    }
 
-                if cell.was_hit_successfully() {
-                    contents[pos.x as usize -1][pos.y as usize -1] = 'X';
-                }
-            }
+    // Like get_contents, but only shows what the opponent has found out:
+    // '.' not fired at yet, 'o' a miss, 'X' a hit.
+    pub fn get_fog_of_war(&self) -> [[char; 9]; 9] {
+        let mut contents = [['.'; 9]; 9];
+        for pos in self.positions(self.shot) {
+            contents[pos.x as usize -1][pos.y as usize -1] = if self.is_occupied(&pos) {'X'} else {'o'};
         }
         contents
     }
 
-    pub fn hit_cell(&mut self, pos:Position) -> bool {
-        if ! self.is_valid_position(&pos) {
-            return false
+    // Like hit_cell, but also says when the hit sank a ship.
+    pub fn fire(&mut self, pos:Position) -> ShotOutcome {
+        if !self.hit_cell(pos) {
+            return ShotOutcome::Miss
+        }
+        let bit: Mask = match self.index(&pos) {
+            Some(i) => 1 << i,
+            None => return ShotOutcome::Miss
+        };
+        match self.ships.iter().find(|ship| ship.mask & bit != 0) {
+            Some(ship) if ship.mask & !self.shot == 0 => ShotOutcome::Sunk(ship.ship_type),
+            _ => ShotOutcome::Hit
         }
-        let mut hit: bool = false;
-        let cell = self.cells.get_mut(&pos).unwrap();
-        cell.hit();
-        if cell.is_occupied() & (cell.get_hitcount()==1) {
-            hit = true
+    }
+
+    pub fn hit_cell(&mut self, pos:Position) -> bool {
+        let i = match self.index(&pos) {
+            Some(i) => i,
+            None => return false
+        };
+        let bit: Mask = 1 << i;
+        // Hitting the same cell twice only counts once.
+        let hit = self.occupied & bit != 0 && self.shot & bit == 0;
+        self.shot |= bit;
+        if hit {
+            self.hits_landed += 1;
         }
         self.update_status();
         hit
     }
 
+    // Progress is kept up to date as ships are placed and hit, so there is nothing to rescan.
     pub fn update_status(&mut self) {
-        let mut total_cells: f32 = 0.0;
-        let mut occupied_cells: f32 = 0.0;
-        let mut successful_hits: f32 = 0.0;
-
-        for cell in self.cells.values() {
-            total_cells += 1.0;
-
-            if cell.is_occupied() {
-                occupied_cells += 1.0;
-
-                if cell.was_hit_successfully() {
-                    successful_hits += 1.0;
-                }
-            }
+        if self.ship_cells == 0 {
+            return  // No fleet yet, nothing to sink
         }
+        let occupied_cells = self.ship_cells as f32;
+        let successful_hits = self.hits_landed as f32;
 
         self.game_progress = (successful_hits/occupied_cells) * 100.0;
+        // Counted, not read off the progress, so rounding can never end a game early.
+        self.game_complete = self.hits_landed == self.ship_cells;
+    }
 
-        if almost::equal(self.game_progress, 100.0) {
-            self.game_complete = true;
-        }
+    // Number of different cells fired at so far.
+    pub fn get_shots(&self) -> u32 {
+        self.shot.count_ones()
+    }
+
+    // Cells left untouched once the fleet went down: the fewer shots it took, the better.
+    pub fn get_score(&self) -> i32 {
+        (self.n_rows * self.n_cols) as i32 - self.get_shots() as i32
     }
 
     pub fn get_progress(&self) -> f32 {
@@ -316,12 +453,20 @@ impl Board {
             for element in row {
                 print!("{} ", element);
             }
-            print!("\n");
+            println!();
         }
     }
 }
 
 
+#[derive(Hash, Eq, PartialEq, Debug, Copy, Clone)]
+pub enum ShotOutcome {
+    Miss,
+    Hit,
+    Sunk(ShipType)
+}
+
+
 #[derive(Hash, Eq, PartialEq, Debug, Copy, Clone)]
 pub struct Position {
     x: i32,
@@ -332,6 +477,30 @@ impl Position {
     pub fn new(x:i32, y:i32) -> Position {
         Position{x,y}
     }
+
+    // Parses board notation such as "C4" (column letter, row number).
+    pub fn from_coord(coord: &str) -> Option<Position> {
+        let mut chars = coord.chars();
+        let col = chars.next()?.to_ascii_uppercase();
+        let x = Board::COLS.iter().position(|c| *c == col)? as i32 + 1;
+        let y: i32 = chars.as_str().parse().ok()?;
+        if Board::ROWS.iter().any(|row| *row as i32 == y) {
+            Some(Position{x,y})
+        }
+        else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let col = self.x.checked_sub(1).and_then(|i| Board::COLS.get(i as usize));
+        match col {
+            Some(col) if self.x > 0 => write!(f, "{}{}", col, self.y),
+            _ => write!(f, "[{},{}]", self.x, self.y)
+        }
+    }
 }
 
 
@@ -373,16 +542,18 @@ impl Cell {
 pub struct Ship {
     ship_type: ShipType,
     cells: Vec<Position>,
+    mask: Mask,
 }
 
 impl Ship {
     pub fn new(ship_type: ShipType) -> Ship {
-        Ship{ship_type, cells:Vec::new()}
+        Ship{ship_type, cells:Vec::new(), mask: 0}
     }
 }
 
 
-#[derive(Hash, Clone, Copy, Eq, PartialEq, Debug, EnumIter)]
+#[derive(Hash, Clone, Copy, Eq, PartialEq, Debug, EnumIter, EnumString)]
+#[strum(ascii_case_insensitive)]
 pub enum ShipType {
     C5,  // Canberra-class Landing Helicopter Dock
     H4,  // Hobart-class Destroyer
@@ -402,7 +573,8 @@ impl ShipType {
 }
 
 
-#[derive(Hash, Eq, PartialEq, Debug, Copy, Clone, EnumIter)]
+#[derive(Hash, Eq, PartialEq, Debug, Copy, Clone, EnumIter, EnumString)]
+#[strum(ascii_case_insensitive)]
 pub enum DirectionName {
     Up,
     Down,
@@ -437,7 +609,14 @@ impl Direction {
             DirectionName::Left => (-1,0),
             DirectionName::Right => (1,0)
         };
-        Direction{name:dir, x:x, y:y}
+        Direction{name:dir, x, y}
+    }
+
+    // The direction taking one step of (x, y), if it is a single step along a row or column.
+    pub fn from_step(x: i32, y: i32) -> Option<Direction> {
+        DirectionName::iter()
+            .map(Direction::new)
+            .find(|dir| dir.x as i32 == x && dir.y as i32 == y)
     }
 
     pub fn get_coord(&self) -> [i8;2] {
@@ -451,11 +630,24 @@ impl Direction {
 }
 
 
-#[derive(Hash, Eq, PartialEq, Debug, Copy, Clone)]
+#[derive(Hash, Eq, PartialEq, Debug, Clone)]
 pub enum GameCommand {
     StartGame,
     Cell(i32,i32),
     Quit,
+    NewMatch,
+    Join(u32),
+    Watch(u32),
+    Resume(u32, String),  // Game and seat token, after a server restart
+    Say(String),
+    Hint,
+    Register(String, String),  // Name and password
+    Login(String, String),
+    Stats(Option<String>),
+    Leaderboard(usize),
+    Place(ShipType, Position, Direction),
+    Auto,
+    Ready,
     InvalidCommand
 }
 
@@ -466,17 +658,77 @@ pub fn command_parser (cmd: &str) -> GameCommand {
         return GameCommand::StartGame
     }
     // Check 2. Is it a cell position?
-    let pattern_cell = r"^CELL:\[[0-9],[0-9]\]$";
+    let pattern_cell = r"^CELL:\[([0-9]),([0-9])\]$";
     let re_cell = Regex::new(pattern_cell).unwrap();
-    if re_cell.is_match(cmd) {
-        let x: i32 = String::from(cmd.chars().nth(6).unwrap()).parse().unwrap();
-        let y: i32 = String::from(cmd.chars().nth(8).unwrap()).parse().unwrap();
-        return GameCommand::Cell(x,y)
+    if let Some(caps) = re_cell.captures(cmd) {
+        // Single digits, so they always parse.
+        if let (Ok(x), Ok(y)) = (caps[1].parse(), caps[2].parse()) {
+            return GameCommand::Cell(x,y)
+        }
     }
     // Check 3. Is it a QUIT command?
     if cmd == "QUIT" {
         return GameCommand::Quit
     }
+    // Check 4. Multiplayer lobby commands
+    if cmd == "NEWMATCH" {
+        return GameCommand::NewMatch
+    }
+    let re_game = Regex::new(r"^(JOIN|WATCH) ([0-9]{1,9})$").unwrap();
+    if let Some(caps) = re_game.captures(cmd) {
+        let id: u32 = caps[2].parse().unwrap();
+        return match &caps[1] {
+            "JOIN" => GameCommand::Join(id),
+            _ => GameCommand::Watch(id)
+        }
+    }
+    let re_resume = Regex::new(r"^RESUME ([0-9]{1,9}) ([0-9a-f]{32})$").unwrap();
+    if let Some(caps) = re_resume.captures(cmd) {
+        return GameCommand::Resume(caps[1].parse().unwrap(), caps[2].to_string())
+    }
+    // Check 5. Fleet placement
+    if cmd == "AUTO" {
+        return GameCommand::Auto
+    }
+    if cmd == "READY" {
+        return GameCommand::Ready
+    }
+    let re_place = Regex::new(r"^PLACE ([A-Za-z0-9]+) ([A-Za-z][0-9]) ([A-Za-z]+)$").unwrap();
+    if let Some(caps) = re_place.captures(cmd) {
+        let shiptype = caps[1].parse::<ShipType>();
+        let pos = Position::from_coord(&caps[2]);
+        let dir = caps[3].parse::<DirectionName>();
+        if let (Ok(shiptype), Some(pos), Ok(dir)) = (shiptype, pos, dir) {
+            return GameCommand::Place(shiptype, pos, Direction::new(dir))
+        }
+        return GameCommand::InvalidCommand
+    }
+    // Check 6. Coaching
+    if cmd == "HINT" {
+        return GameCommand::Hint
+    }
+    // Check 7. Accounts, players and their statistics
+    let re_account = Regex::new(r"^(REGISTER|LOGIN) ([A-Za-z0-9_-]{1,20}) (\S{1,64})$").unwrap();
+    if let Some(caps) = re_account.captures(cmd) {
+        let (name, password) = (caps[2].to_string(), caps[3].to_string());
+        return match &caps[1] {
+            "REGISTER" => GameCommand::Register(name, password),
+            _ => GameCommand::Login(name, password)
+        }
+    }
+    let re_stats = Regex::new(r"^STATS(?: ([A-Za-z0-9_-]{1,20}))?$").unwrap();
+    if let Some(caps) = re_stats.captures(cmd) {
+        return GameCommand::Stats(caps.get(1).map(|m| m.as_str().to_string()))
+    }
+    let re_leaderboard = Regex::new(r"^LEADERBOARD(?: ([0-9]{1,3}))?$").unwrap();
+    if let Some(caps) = re_leaderboard.captures(cmd) {
+        let n = caps.get(1).map_or(10, |m| m.as_str().parse().unwrap());
+        return GameCommand::Leaderboard(n)
+    }
+    // Check 8. Is it a chat message?
+    if let Some(text) = cmd.strip_prefix("SAY ") {
+        return GameCommand::Say(text.to_string())
+    }
 
     GameCommand::InvalidCommand
 }
@@ -486,13 +738,14 @@ pub enum CommandResult {
     Success(String),
     Failure(String),
     Message(String),
-    GameComplete(i32),  // TODO: Implement some kind of score to return
+    GameComplete(i32),  // Score, see Board::get_score
     Some(Board),
     None,
     Quit
 }
 
 
+#[allow(clippy::needless_return)]
 pub fn command_handler(board: &mut Option<Board>, cmd:GameCommand) -> CommandResult {
     match cmd {
         GameCommand::StartGame => {
@@ -505,14 +758,14 @@ pub fn command_handler(board: &mut Option<Board>, cmd:GameCommand) -> CommandRes
         }
         GameCommand::Cell(x,y) => {
             // Make sure a board exists
-            if let None = board {
+            if board.is_none() {
                 return CommandResult::Failure(String::from("No game started yet."));
             }
             let board = board.as_mut().unwrap();
             let result = board.hit_cell(Position{x,y});
             if result {
                 if board.is_game_complete() {
-                    return CommandResult::GameComplete(0);
+                    return CommandResult::GameComplete(board.get_score());
                     }
                 return CommandResult::Success(String::from("HIT"))
             }
@@ -520,9 +773,48 @@ pub fn command_handler(board: &mut Option<Board>, cmd:GameCommand) -> CommandRes
                 return CommandResult::Failure(String::from("MISS"))
             }
         }
+        GameCommand::Place(shiptype, pos, dir) => {
+            let board = match board {
+                Some(board) => board,
+                None => return CommandResult::Failure(String::from("No fleet to place."))
+            };
+            match board.place_ship(shiptype, &pos, &dir) {
+                Ok(()) => return CommandResult::Success(format!("{:?} placed.", shiptype)),
+                Err(msg) => return CommandResult::Failure(format!("{}.", msg))
+            }
+        }
+        GameCommand::Auto => {
+            let board = match board {
+                Some(board) => board,
+                None => return CommandResult::Failure(String::from("No fleet to place."))
+            };
+            board.place_remaining_auto();
+            return CommandResult::Success(String::from("Fleet placed."))
+        }
+        GameCommand::Ready => {
+            let board = match board {
+                Some(board) => board,
+                None => return CommandResult::Failure(String::from("No fleet to place."))
+            };
+            let unplaced = board.get_unplaced_ships();
+            if !unplaced.is_empty() {
+                let names: Vec<String> = unplaced.iter().map(|s| format!("{:?}", s)).collect();
+                return CommandResult::Failure(format!("Fleet incomplete, still to place: {}.", names.join(" ")))
+            }
+            if !BoardConfig::validate_manual(&board.get_layout()) {
+                return CommandResult::Failure(String::from("Invalid fleet."))
+            }
+            return CommandResult::Success(String::from("READY"))
+        }
         GameCommand::Quit => {
             return CommandResult::Quit
         }
+        GameCommand::NewMatch | GameCommand::Join(_) | GameCommand::Watch(_) | GameCommand::Resume(..)
+            | GameCommand::Say(_) | GameCommand::Hint
+            | GameCommand::Register(..) | GameCommand::Login(..) | GameCommand::Stats(_) | GameCommand::Leaderboard(_) => {
+            // Lobby commands are handled by the server, there is no board to act on.
+            return CommandResult::None
+        }
         GameCommand::InvalidCommand => {
             return CommandResult::None
         }
@@ -535,16 +827,29 @@ pub fn command_handler(board: &mut Option<Board>, cmd:GameCommand) -> CommandRes
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
     #[test]
     fn board_cell_relations() {
         let b = Board::new();
         let p = Position::new(2,2);
-        let cell = b.cells.get(&p);
+        let cell = b.cell(&p);
         let something = match cell {
             Some(cell) => true,
             None => false
         };
-        assert_eq!(something, true);
+        assert!(something);
+
+        // Cells are views of the board, so they follow placement and shots.
+        let mut b = Board::new();
+        b.place_ship(ShipType::A2, &Position::new(2,2), &Direction::new(DirectionName::Right)).unwrap();
+        b.hit_cell(Position::new(3,2));
+        let (next, cell) = b.get_next_cell(p, Direction::new(DirectionName::Right)).unwrap();
+        assert_eq!(next, Position::new(3,2));
+        assert!(cell.is_occupied() && cell.was_hit_successfully());
+        assert!(!b.cell(&p).unwrap().was_hit_successfully());
+        assert!(b.cell(&Position::new(10,2)).is_none());
     }
 
     #[test]
@@ -552,5 +857,147 @@ mod tests {
         assert_eq!(command_parser("STARTGAME"), GameCommand::StartGame);
         assert_eq!(command_parser("CELL:[3,1]"), GameCommand::Cell(3,1));
         assert_eq!(command_parser("QUIT"), GameCommand::Quit);
+        assert_eq!(command_parser("NEWMATCH"), GameCommand::NewMatch);
+        assert_eq!(command_parser("JOIN 12"), GameCommand::Join(12));
+        assert_eq!(command_parser("WATCH 3"), GameCommand::Watch(3));
+        assert_eq!(command_parser("RESUME 3 0123456789abcdef0123456789abcdef"),
+                   GameCommand::Resume(3, String::from("0123456789abcdef0123456789abcdef")));
+        assert_eq!(command_parser("RESUME 3 guess"), GameCommand::InvalidCommand);
+        assert_eq!(command_parser("WATCH"), GameCommand::InvalidCommand);
+        assert_eq!(command_parser("SAY good luck"), GameCommand::Say(String::from("good luck")));
+        assert_eq!(command_parser("PLACE C5 A1 Right"),
+                   GameCommand::Place(ShipType::C5, Position::new(1,1), Direction::new(DirectionName::Right)));
+        assert_eq!(command_parser("PLACE a2 i9 up"),
+                   GameCommand::Place(ShipType::A2, Position::new(9,9), Direction::new(DirectionName::Up)));
+        assert_eq!(command_parser("PLACE X9 A1 Right"), GameCommand::InvalidCommand);
+        assert_eq!(command_parser("READY"), GameCommand::Ready);
+        assert_eq!(command_parser("HINT"), GameCommand::Hint);
+        assert_eq!(command_parser("REGISTER ann_1 s3cret!"),
+                   GameCommand::Register(String::from("ann_1"), String::from("s3cret!")));
+        assert_eq!(command_parser("LOGIN ann pass word"), GameCommand::InvalidCommand);
+        assert_eq!(command_parser("STATS"), GameCommand::Stats(None));
+        assert_eq!(command_parser("STATS bob"), GameCommand::Stats(Some(String::from("bob"))));
+        assert_eq!(command_parser("LEADERBOARD"), GameCommand::Leaderboard(10));
+        assert_eq!(command_parser("LEADERBOARD 3"), GameCommand::Leaderboard(3));
+    }
+
+    #[test]
+    fn manual_placement() {
+        let mut board = Board::new();
+        let right = Direction::new(DirectionName::Right);
+        let down = Direction::new(DirectionName::Down);
+        assert!(board.place_ship(ShipType::C5, &Position::new(1,1), &right).is_ok());
+        assert!(board.place_ship(ShipType::C5, &Position::new(1,3), &right).is_err());
+        assert!(board.place_ship(ShipType::H4, &Position::new(2,1), &down).is_err());
+        assert!(board.place_ship(ShipType::H4, &Position::new(9,7), &down).is_err());
+        assert!(board.place_ship(ShipType::H4, &Position::new(9,6), &down).is_ok());
+        assert_eq!(board.get_unplaced_ships(), vec![ShipType::L3, ShipType::A2]);
+
+        board.place_remaining_auto();
+        assert!(board.get_unplaced_ships().is_empty());
+        let layout = board.get_layout();
+        assert_eq!(layout.get(&ShipType::C5), Some(&(Position::new(1,1), right)));
+        assert!(BoardConfig::validate_manual(&layout));
+    }
+
+    #[test]
+    fn game_progress_tracking() {
+        let mut board = Board::new();
+        board.setup(BoardConfig::Auto).unwrap();
+        let occupied = board.get_occupied_cells();
+        assert_eq!(occupied.len(), 14);
+        assert_eq!(board.get_unoccupied_cells().len(), 81 - 14);
+
+        let water = *board.get_unoccupied_cells().iter().next().unwrap();
+        assert!(!board.hit_cell(water));
+        assert!(!board.hit_cell(Position::new(0,4)));
+        assert_eq!(board.get_progress(), 0.0);
+
+        let mut hits = 0;
+        for pos in occupied.iter() {
+            assert!(!board.is_game_complete());
+            assert!(board.hit_cell(*pos));
+            assert!(!board.hit_cell(*pos));  // Hitting the same cell twice only counts once
+            hits += 1;
+            assert!(almost::equal(board.get_progress(), hits as f32 / 14.0 * 100.0));
+        }
+        assert!(board.is_game_complete());
+        assert!(ShipType::iter().all(|shiptype| board.is_ship_sunk(shiptype)));
+    }
+
+    #[test]
+    fn position_coordinates() {
+        assert_eq!(Position::from_coord("C4"), Some(Position::new(3,4)));
+        assert_eq!(Position::from_coord("i9"), Some(Position::new(9,9)));
+        assert_eq!(Position::from_coord("J1"), None);
+        assert_eq!(Position::from_coord("A0"), None);
+        assert_eq!(Position::new(1,7).to_string(), "A7");
+        assert_eq!(Position::new(0,7).to_string(), "[0,7]");
+    }
+
+    fn ship_type() -> impl Strategy<Value = ShipType> {
+        prop::sample::select(ShipType::iter().collect::<Vec<_>>())
+    }
+
+    fn direction() -> impl Strategy<Value = Direction> {
+        prop::sample::select(DirectionName::iter().collect::<Vec<_>>()).prop_map(Direction::new)
+    }
+
+    // Mostly around the board, sometimes anywhere at all.
+    fn coordinate() -> impl Strategy<Value = i32> {
+        prop_oneof![4 => -2..12i32, 1 => any::<i32>()]
+    }
+
+    proptest! {
+        #[test]
+        fn any_line_parses_without_panicking(line in "\\PC*") {
+            command_parser(&line);
+        }
+
+        #[test]
+        fn near_misses_parse_without_panicking(line in "(CELL:\\[|PLACE |JOIN |WATCH |RESUME |LEADERBOARD |STATS |SAY )[ -~]{0,40}") {
+            command_parser(&line);
+        }
+
+        #[test]
+        fn cells_parse_back(x in 0..10i32, y in 0..10i32) {
+            prop_assert_eq!(command_parser(&format!("CELL:[{},{}]", x, y)), GameCommand::Cell(x, y));
+        }
+
+        #[test]
+        fn ships_never_overlap(moves in prop::collection::vec((ship_type(), coordinate(), coordinate(), direction()), 0..20)) {
+            let mut board = Board::new();
+            for (shiptype, x, y, dir) in moves {
+                let _ = board.place_ship(shiptype, &Position::new(x, y), &dir);
+            }
+            let unplaced = board.get_unplaced_ships();
+            let size: usize = ShipType::iter().filter(|s| !unplaced.contains(s)).map(|s| s.get_size() as usize).sum();
+            prop_assert_eq!(board.get_occupied_cells().len(), size);
+            prop_assert!(board.get_occupied_cells().iter().all(|pos| board.is_valid_position(pos)));
+            prop_assert!(BoardConfig::validate_manual(&board.get_layout()));
+
+            board.place_remaining_auto();
+            prop_assert!(board.get_unplaced_ships().is_empty());
+            prop_assert_eq!(board.get_occupied_cells().len(), 14);
+        }
+
+        #[test]
+        fn games_end_exactly_when_the_fleet_is_sunk(seed: u64, shots in prop::collection::vec((coordinate(), coordinate()), 0..150)) {
+            let mut board = Board::new();
+            board.setup_with_rng(BoardConfig::Auto, &mut StdRng::seed_from_u64(seed)).unwrap();
+            let fleet = board.get_occupied_cells();
+            let mut sunk = HashSet::new();
+            let mut progress = 0.0;
+            let fire_all = fleet.iter().map(|pos| (pos.x, pos.y)).collect::<Vec<_>>();
+            for (x, y) in shots.into_iter().chain(fire_all) {
+                let pos = Position::new(x, y);
+                prop_assert_eq!(board.hit_cell(pos), fleet.contains(&pos) && sunk.insert(pos));
+                prop_assert!(board.get_progress() >= progress);
+                progress = board.get_progress();
+                prop_assert_eq!(board.is_game_complete(), sunk.len() == fleet.len());
+            }
+            prop_assert!(board.is_game_complete());
+            prop_assert!(board.get_shots() <= 81);
+        }
     }
 }
@@ -0,0 +1,133 @@
+// Protection against clients that flood the server: a cap on connections per
+// address, a token bucket per connection for commands, a longest accepted
+// line, and temporary bans for addresses that keep sending invalid commands.
+//
+// The limits come from the config file (see config.rs), so RELOAD changes
+// them for everybody, connected or not.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Limits {
+    pub max_connections_per_ip: usize,
+    pub commands_per_second: f64,
+    pub command_burst: f64,          // Commands allowed in a row before the rate applies
+    pub max_line_length: usize,      // In bytes
+    pub invalid_commands_per_minute: f64,  // More than this gets the address banned
+    pub ban_seconds: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits{max_connections_per_ip: 8, commands_per_second: 10.0, command_burst: 20.0, max_line_length: 1024,
+               invalid_commands_per_minute: 20.0, ban_seconds: 300}
+    }
+}
+
+
+// Holds up to `capacity` tokens, refilled at `rate` a second. Each command takes one.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64) -> TokenBucket {
+        TokenBucket{tokens: capacity, last: Instant::now()}
+    }
+
+    pub fn take(&mut self, rate: f64, capacity: f64) -> bool {
+        self.take_at(Instant::now(), rate, capacity)
+    }
+
+    fn take_at(&mut self, now: Instant, rate: f64, capacity: f64) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(capacity);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return true
+        }
+        false
+    }
+}
+
+
+// Connections and bans per address.
+#[derive(Debug, Default)]
+pub struct Guard {
+    connections: HashMap<IpAddr, usize>,
+    bans: HashMap<IpAddr, Instant>,  // Banned until then
+}
+
+impl Guard {
+    // Counts a new connection from `ip`, unless it is banned or has too many already.
+    pub fn admit(&mut self, ip: IpAddr, limits: &Limits) -> Result<(), String> {
+        let now = Instant::now();
+        self.bans.retain(|_, until| *until > now);
+        if let Some(until) = self.bans.get(&ip) {
+            return Err(format!("Banned for another {} seconds.", until.duration_since(now).as_secs() + 1))
+        }
+        let count = self.connections.entry(ip).or_insert(0);
+        if *count >= limits.max_connections_per_ip {
+            return Err(format!("Too many connections from {} (at most {}).", ip, limits.max_connections_per_ip))
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    pub fn release(&mut self, ip: IpAddr) {
+        if let Some(count) = self.connections.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                self.connections.remove(&ip);
+            }
+        }
+    }
+
+    pub fn ban(&mut self, ip: IpAddr, length: Duration) {
+        self.bans.insert(ip, Instant::now() + length);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_refill_at_the_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket{tokens: 2.0, last: start};
+        assert!(bucket.take_at(start, 1.0, 2.0));
+        assert!(bucket.take_at(start, 1.0, 2.0));
+        assert!(!bucket.take_at(start, 1.0, 2.0));
+        assert!(!bucket.take_at(start + Duration::from_millis(500), 1.0, 2.0));
+        assert!(bucket.take_at(start + Duration::from_millis(1000), 1.0, 2.0));
+        // Never more than the capacity, however long it waits.
+        let later = start + Duration::from_secs(60);
+        assert!(bucket.take_at(later, 1.0, 2.0) && bucket.take_at(later, 1.0, 2.0));
+        assert!(!bucket.take_at(later, 1.0, 2.0));
+    }
+
+    #[test]
+    fn addresses_are_capped_and_banned() {
+        let limits = Limits{max_connections_per_ip: 2, ..Limits::default()};
+        let (ip, other): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let mut guard = Guard::default();
+        assert!(guard.admit(ip, &limits).is_ok());
+        assert!(guard.admit(ip, &limits).is_ok());
+        assert_eq!(guard.admit(ip, &limits).unwrap_err(), "Too many connections from 10.0.0.1 (at most 2).");
+        guard.release(ip);
+        assert!(guard.admit(ip, &limits).is_ok());
+
+        guard.ban(other, Duration::from_secs(60));
+        assert_eq!(guard.admit(other, &limits).unwrap_err(), "Banned for another 60 seconds.");
+        guard.ban(other, Duration::from_secs(0));
+        assert!(guard.admit(other, &limits).is_ok());
+    }
+}
@@ -0,0 +1,122 @@
+// Leveled server log, one event per line in logfmt:
+//
+//   ts=2026-10-19T05:17:00.123Z level=info event=command conn=3 game=1 cmd="CELL:[3,1]" result=success latency_us=85
+//
+// Events are logged with log_event!, naming the level, the event and any
+// number of key = value fields. The level can be changed while running.
+
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use strum_macros::{Display, EnumString};
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, EnumString, Display)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+
+// Shows a missing value as "-".
+pub struct Opt<T>(pub Option<T>);
+
+impl<T: Display> Display for Opt<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0 {
+            Some(value) => value.fmt(f),
+            None => write!(f, "-")
+        }
+    }
+}
+
+
+// Values with spaces, quotes or '=' are quoted, so every line splits back into fields.
+fn quote(value: &str) -> String {
+    if !value.is_empty() && !value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=' || c.is_control()) {
+        return value.to_string()
+    }
+    let escaped: String = value.chars().map(|c| match c {
+        '"' => String::from("\\\""),
+        '\\' => String::from("\\\\"),
+        '\n' => String::from("\\n"),
+        c if c.is_control() => format!("\\u{{{:x}}}", c as u32),
+        c => c.to_string()
+    }).collect();
+    format!("\"{}\"", escaped)
+}
+
+// UTC time in RFC 3339, down to milliseconds.
+fn timestamp(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs() as i64;
+    let (days, rest) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Days to a civil date, after Howard Hinnant's algorithm.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day,
+            rest / 3600, rest % 3600 / 60, rest % 60, since.subsec_millis())
+}
+
+pub fn format_line(time: SystemTime, level: Level, event: &str, fields: &[(&str, &dyn Display)]) -> String {
+    let mut line = format!("ts={} level={} event={}", timestamp(time), level, quote(event));
+    for (key, value) in fields {
+        line.push_str(&format!(" {}={}", key, quote(&value.to_string())));
+    }
+    line
+}
+
+pub fn write(level: Level, event: &str, fields: &[(&str, &dyn Display)]) {
+    println!("{}", format_line(SystemTime::now(), level, event, fields));
+}
+
+
+#[macro_export]
+macro_rules! log_event {
+    ($level:ident, $event:expr $(, $key:ident = $value:expr)* $(,)?) => {
+        if $crate::logging::enabled($crate::logging::Level::$level) {
+            $crate::logging::write($crate::logging::Level::$level, $event,
+                                   &[$((stringify!($key), &$value as &dyn std::fmt::Display)),*]);
+        }
+    };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn lines_are_logfmt() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_792_386_620_123);
+        let line = format_line(time, Level::Warn, "command", &[("conn", &3), ("game", &Opt::<u32>(None)),
+                                                                ("cmd", &"SAY \"hi\" there")]);
+        assert_eq!(line, r#"ts=2026-10-19T05:10:20.123Z level=warn event=command conn=3 game=- cmd="SAY \"hi\" there""#);
+        assert_eq!(quote(""), "\"\"");
+        assert_eq!(timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)), "2000-02-29T00:00:00.000Z");
+        assert!(Level::Error < Level::Debug);
+        assert_eq!("INFO".parse::<Level>().unwrap(), Level::Info);
+    }
+}
@@ -0,0 +1,208 @@
+// Server counters, served in the Prometheus text format on a local port:
+//
+//   curl localhost:9100/metrics
+//
+// Counters live in METRICS and are bumped from wherever things happen;
+// gauges about the lobby are read from it when scraped.
+
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::log_event;
+use crate::server::SharedLobby;
+
+
+// Upper bounds, in seconds, of the game duration histogram buckets.
+const DURATION_BUCKETS: [u64; 8] = [30, 60, 120, 300, 600, 1200, 1800, 3600];
+
+// Seconds over which commands per second are averaged.
+const RATE_WINDOW: usize = 60;
+
+
+struct Histogram {
+    counts: [u64; DURATION_BUCKETS.len()],  // Not cumulative
+    count: u64,
+    sum: f64,
+}
+
+
+pub struct Metrics {
+    connections: AtomicU64,
+    commands: AtomicU64,
+    parse_failures: AtomicU64,
+    games_started: AtomicU64,
+    games_finished: AtomicU64,
+    rate_limited: AtomicU64,
+    bans: AtomicU64,
+    durations: Mutex<Histogram>,
+    recent: Mutex<[(u64, u64); RATE_WINDOW]>,  // Commands per second, as (second, count)
+}
+
+pub static METRICS: Metrics = Metrics::new();
+
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub const fn new() -> Metrics {
+        Metrics{
+            connections: AtomicU64::new(0), commands: AtomicU64::new(0), parse_failures: AtomicU64::new(0),
+            games_started: AtomicU64::new(0), games_finished: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0), bans: AtomicU64::new(0),
+            durations: Mutex::new(Histogram{counts: [0; DURATION_BUCKETS.len()], count: 0, sum: 0.0}),
+            recent: Mutex::new([(0, 0); RATE_WINDOW]),
+        }
+    }
+
+    pub fn connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn command(&self, parsed: bool) {
+        self.command_at(now_secs(), parsed);
+    }
+
+    fn command_at(&self, second: u64, parsed: bool) {
+        self.commands.fetch_add(1, Ordering::Relaxed);
+        if !parsed {
+            self.parse_failures.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut recent = self.recent.lock().unwrap();
+        let slot = &mut recent[second as usize % RATE_WINDOW];
+        if slot.0 != second {
+            *slot = (second, 0);
+        }
+        slot.1 += 1;
+    }
+
+    pub fn rate_limited(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn banned(&self) {
+        self.bans.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn game_started(&self) {
+        self.games_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn game_finished(&self, duration: Duration) {
+        self.games_finished.fetch_add(1, Ordering::Relaxed);
+        let secs = duration.as_secs_f64();
+        let mut durations = self.durations.lock().unwrap();
+        if let Some(i) = DURATION_BUCKETS.iter().position(|b| secs <= *b as f64) {
+            durations.counts[i] += 1;
+        }
+        durations.count += 1;
+        durations.sum += secs;
+    }
+
+    // Average over the last RATE_WINDOW seconds, the current one included.
+    fn commands_per_second(&self, now: u64) -> f64 {
+        let recent = self.recent.lock().unwrap();
+        let total: u64 = recent.iter().filter(|(second, _)| *second + RATE_WINDOW as u64 > now).map(|(_, n)| n).sum();
+        total as f64 / RATE_WINDOW as f64
+    }
+
+    pub fn render(&self, connections: usize, games: usize) -> String {
+        self.render_at(now_secs(), connections, games)
+    }
+
+    fn render_at(&self, now: u64, connections: usize, games: usize) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            out += &format!("# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value);
+        };
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed).to_string();
+        metric("battleship_connections", "gauge", "Clients connected right now.", connections.to_string());
+        metric("battleship_connections_total", "counter", "Clients connected since the start.", get(&self.connections));
+        metric("battleship_games_in_progress", "gauge", "Games running right now.", games.to_string());
+        metric("battleship_games_started_total", "counter", "Games started.", get(&self.games_started));
+        metric("battleship_games_finished_total", "counter", "Games played to a winner.", get(&self.games_finished));
+        metric("battleship_commands_total", "counter", "Command lines received.", get(&self.commands));
+        metric("battleship_commands_per_second", "gauge", "Commands per second over the last minute.",
+               format!("{:.3}", self.commands_per_second(now)));
+        metric("battleship_parse_failures_total", "counter", "Command lines that did not parse.", get(&self.parse_failures));
+        metric("battleship_rate_limited_total", "counter", "Commands dropped for coming too fast.", get(&self.rate_limited));
+        metric("battleship_bans_total", "counter", "Addresses banned for invalid command spam.", get(&self.bans));
+
+        let durations = self.durations.lock().unwrap();
+        out += "# HELP battleship_game_duration_seconds Time from the start of a game to its winning shot.\n";
+        out += "# TYPE battleship_game_duration_seconds histogram\n";
+        let mut cumulative = 0;
+        for (bound, count) in DURATION_BUCKETS.iter().zip(durations.counts.iter()) {
+            cumulative += count;
+            out += &format!("battleship_game_duration_seconds_bucket{{le=\"{}\"}} {}\n", bound, cumulative);
+        }
+        out += &format!("battleship_game_duration_seconds_bucket{{le=\"+Inf\"}} {}\n", durations.count);
+        out += &format!("battleship_game_duration_seconds_sum {}\n", durations.sum);
+        out += &format!("battleship_game_duration_seconds_count {}\n", durations.count);
+        out
+    }
+}
+
+
+pub fn serve(listener: TcpListener, lobby: SharedLobby) {
+    let server = match tiny_http::Server::from_listener(listener, None) {
+        Ok(server) => server,
+        Err(e) => return log_event!(Error, "metrics_failed", error = e)
+    };
+    let content_type = tiny_http::Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+    for request in server.incoming_requests() {
+        let response = if request.url() == "/metrics" {
+            let (connections, games) = {
+                let lobby = lobby.lock().unwrap();
+                (lobby.get_client_count(), lobby.get_games().len())
+            };
+            tiny_http::Response::from_string(METRICS.render(connections, games)).with_header(content_type.clone())
+        }
+        else {
+            tiny_http::Response::from_string("Not found.\n").with_status_code(404)
+        };
+        request.respond(response).ok();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_are_exposed() {
+        let metrics = Metrics::new();
+        metrics.connection_opened();
+        for second in [100, 100, 159] {
+            metrics.command_at(second, true);
+        }
+        metrics.command_at(159, false);
+        metrics.game_started();
+        metrics.banned();
+        metrics.game_finished(Duration::from_secs(45));
+        metrics.game_finished(Duration::from_secs(4000));
+
+        let text = metrics.render_at(159, 2, 1);
+        assert!(text.contains("\nbattleship_connections 2\n"));
+        assert!(text.contains("\nbattleship_connections_total 1\n"));
+        assert!(text.contains("\nbattleship_commands_total 4\n"));
+        assert!(text.contains("\nbattleship_parse_failures_total 1\n"));
+        assert!(text.contains("\nbattleship_bans_total 1\n"));
+        assert!(text.contains("\nbattleship_commands_per_second 0.067\n"));
+        assert!(text.contains("\nbattleship_game_duration_seconds_bucket{le=\"30\"} 0\n"));
+        assert!(text.contains("\nbattleship_game_duration_seconds_bucket{le=\"60\"} 1\n"));
+        assert!(text.contains("\nbattleship_game_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("\nbattleship_game_duration_seconds_sum 4045\n"));
+        assert_eq!(metrics.commands_per_second(161), 1.0 / 60.0 * 2.0);
+    }
+}
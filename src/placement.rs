@@ -0,0 +1,132 @@
+// Ways for the computer to set up its fleet other than plain random
+// placement, which probability based shooters (see solver) exploit.
+//
+// Each policy draws a number of random fleets and keeps the one it likes
+// best, so fleets still vary from game to game.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use rand::Rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::{Board, BoardConfig, Direction, Position, ShipType};
+use crate::solver::{ProbabilityMap, Solver};
+use crate::strategy::FogGrid;
+
+
+// Random fleets drawn for each policy to pick from.
+const CANDIDATES: usize = 64;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, EnumIter, EnumString, Display)]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum PlacementPolicy {
+    Random,
+    EdgeHugging,  // Ships along the sides of the board
+    SpreadOut,    // Ships as far from each other as they can be
+    Clustered,    // Ships packed into a small corner of the board
+    AntiHeatmap,  // Ships where a probability based shooter looks last
+}
+
+impl PlacementPolicy {
+    // A complete fleet layout chosen by this policy.
+    pub fn choose_layout<R: Rng>(&self, rng: &mut R) -> HashMap<ShipType, (Position, Direction)> {
+        let candidates = if *self == PlacementPolicy::Random {1} else {CANDIDATES};
+        let mut best: Option<(f64, Board)> = None;
+        for _ in 0..candidates {
+            let mut board = Board::new();
+            board.setup_with_rng(BoardConfig::Auto, rng).unwrap();
+            let score = self.score(&board);
+            if best.as_ref().is_none_or(|(s, _)| score > *s) {
+                best = Some((score, board));
+            }
+        }
+        best.unwrap().1.get_layout()
+    }
+
+    // How much the policy likes a fleet; higher is better.
+    fn score(&self, board: &Board) -> f64 {
+        let cells: Vec<Position> = board.get_occupied_cells().into_iter().collect();
+        match self {
+            PlacementPolicy::Random => 0.0,
+            PlacementPolicy::EdgeHugging => {
+                cells.iter().filter(|p| p.x == 1 || p.x == 9 || p.y == 1 || p.y == 9).count() as f64
+            }
+            PlacementPolicy::SpreadOut => {
+                // Closest any two ships come to each other, then how far apart they are overall.
+                let ships: Vec<Vec<Position>> = board.ships.iter().map(|s| board.positions(s.mask)).collect();
+                let mut closest = i32::MAX;
+                let mut total = 0;
+                for (i, a) in ships.iter().enumerate() {
+                    for b in ships[i+1..].iter() {
+                        let gap = a.iter().flat_map(|p| b.iter().map(move |q| distance(p, q))).min().unwrap();
+                        closest = closest.min(gap);
+                        total += gap;
+                    }
+                }
+                closest as f64 * 100.0 + total as f64
+            }
+            PlacementPolicy::Clustered => {
+                let width = cells.iter().map(|p| p.x).max().unwrap() - cells.iter().map(|p| p.x).min().unwrap() + 1;
+                let height = cells.iter().map(|p| p.y).max().unwrap() - cells.iter().map(|p| p.y).min().unwrap() + 1;
+                -(width * height) as f64
+            }
+            PlacementPolicy::AntiHeatmap => {
+                let heatmap = prior_heatmap();
+                -cells.iter().map(|p| heatmap.get(p)).sum::<f64>()
+            }
+        }
+    }
+}
+
+
+// Chebyshev distance: 1 for cells that touch, even diagonally.
+fn distance(a: &Position, b: &Position) -> i32 {
+    (a.x - b.x).abs().max((a.y - b.y).abs())
+}
+
+
+// Where the solver expects ships before the first shot. Worked out once, from a fixed seed.
+fn prior_heatmap() -> &'static ProbabilityMap {
+    static HEATMAP: OnceLock<ProbabilityMap> = OnceLock::new();
+    HEATMAP.get_or_init(|| {
+        let solver = Solver{samples: 20_000, exact_limit: 0};
+        solver.solve(&FogGrid::new(), &mut StdRng::seed_from_u64(0))
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn policies_shape_the_fleet() {
+        let mut rng = StdRng::seed_from_u64(5);
+        for policy in PlacementPolicy::iter() {
+            let mut board = Board::new();
+            board.setup_with_rng(BoardConfig::Policy(policy), &mut rng).unwrap();
+            assert!(board.get_unplaced_ships().is_empty());
+            assert!(BoardConfig::validate_manual(&board.get_layout()));
+        }
+
+        // Averaged over a few fleets, each policy beats random placement at its own game.
+        let average = |policy: PlacementPolicy, judge: PlacementPolicy, rng: &mut StdRng| {
+            (0..10).map(|_| {
+                let mut board = Board::new();
+                board.setup_with_rng(BoardConfig::Manual(policy.choose_layout(rng)), rng).unwrap();
+                judge.score(&board)
+            }).sum::<f64>() / 10.0
+        };
+        for policy in [PlacementPolicy::EdgeHugging, PlacementPolicy::SpreadOut,
+                       PlacementPolicy::Clustered, PlacementPolicy::AntiHeatmap].iter() {
+            assert!(average(*policy, *policy, &mut rng) > average(PlacementPolicy::Random, *policy, &mut rng));
+        }
+        assert_eq!("anti-heatmap".parse::<PlacementPolicy>(), Ok(PlacementPolicy::AntiHeatmap));
+        assert_eq!(PlacementPolicy::EdgeHugging.to_string(), "edge-hugging");
+    }
+}
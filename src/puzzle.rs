@@ -0,0 +1,425 @@
+// Battleship solitaire (Bimaru): the fleet is hidden on the board, and the
+// player is told how many ship segments there are in every row and column,
+// plus a few cells that are given away. Ships may not touch each other, not
+// even diagonally. The puzzle is to work out where the whole fleet is.
+//
+// Puzzles are generated from a random fleet, revealing cells until the
+// solution is the only one left. The solver works with two kinds of steps:
+// logical deductions (rows and columns that are full or can only be full,
+// water around ships, ships with a single place left to go) and, when those
+// run out, trying each place for the most constrained ship in turn.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+use strum::IntoEnumIterator;
+use strum_macros::{Display, EnumIter, EnumString};
+
+use crate::{Board, BoardConfig, Mask, Position, ShipType};
+use crate::solver::{bit, placements};
+use crate::strategy::FogGrid;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, EnumIter, EnumString, Display)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum Difficulty {
+    Easy,    // Solvable by deduction alone, with a couple of extra cells given
+    Medium,  // Solvable by deduction alone, with no more cells given than needed
+    Hard,    // As few cells given as possible, may need trial and error
+}
+
+
+// Every cell in row `y` (or column `x`), 1 to 9.
+fn row_mask(y: i32) -> Mask {
+    (1..=9).fold(0, |m, x| m | bit(&Position::new(x,y)))
+}
+
+fn col_mask(x: i32) -> Mask {
+    (1..=9).fold(0, |m, y| m | bit(&Position::new(x,y)))
+}
+
+// Cells touching the mask, diagonals included, not counting the mask itself.
+fn halo(mask: Mask) -> Mask {
+    let mut around = 0;
+    for pos in FogGrid::all_positions() {
+        if mask & bit(&pos) != 0 {
+            for (dx, dy) in [(-1,-1), (0,-1), (1,-1), (-1,0), (1,0), (-1,1), (0,1), (1,1)].iter() {
+                let next = Position::new(pos.x + dx, pos.y + dy);
+                if FogGrid::contains(&next) {
+                    around |= bit(&next);
+                }
+            }
+        }
+    }
+    around & !mask
+}
+
+fn positions(mask: Mask) -> Vec<Position> {
+    FogGrid::all_positions().filter(|pos| mask & bit(pos) != 0).collect()
+}
+
+
+// What is known of the board part way through solving.
+#[derive(Clone)]
+struct State {
+    ship: Mask,
+    water: Mask,
+    placed: Vec<Mask>,  // Ships whose position is settled
+    left: Vec<Vec<(Mask, Mask)>>,  // Places (and their surroundings) for each ship still to settle
+}
+
+enum Progress {
+    Stuck,
+    Solved,
+    Contradiction,
+}
+
+
+#[derive(Clone, Debug)]
+pub struct Puzzle {
+    rows: [u32; 9],
+    cols: [u32; 9],
+    givens: Vec<(Position, bool)>,  // Revealed cells, true for a ship segment
+    solution: Mask,
+    difficulty: Difficulty,
+}
+
+impl Puzzle {
+    pub fn generate<R: Rng>(difficulty: Difficulty, rng: &mut R) -> Puzzle {
+        // Some fleets give themselves away whatever is done; a hard puzzle
+        // should need some trial and error, if one turns up soon enough.
+        let mut puzzle = Self::generate_once(difficulty, rng);
+        for _ in 0..20 {
+            if difficulty != Difficulty::Hard || !puzzle.is_logical() {
+                break;
+            }
+            puzzle = Self::generate_once(difficulty, rng);
+        }
+        puzzle
+    }
+
+    fn generate_once<R: Rng>(difficulty: Difficulty, rng: &mut R) -> Puzzle {
+        let solution = Self::random_fleet(rng);
+        let mut puzzle = Puzzle{
+            rows: [0; 9], cols: [0; 9], givens: Vec::new(), solution, difficulty,
+        };
+        for i in 0..9 {
+            puzzle.rows[i] = (solution & row_mask(i as i32 + 1)).count_ones();
+            puzzle.cols[i] = (solution & col_mask(i as i32 + 1)).count_ones();
+        }
+
+        // Give away cells until nothing else fits the clues.
+        loop {
+            let solutions = puzzle.solutions(2);
+            let other = match solutions.iter().find(|s| **s != solution) {
+                Some(other) => *other,
+                None => break
+            };
+            let differ = positions(other ^ solution);
+            puzzle.reveal(*differ.choose(rng).unwrap());
+        }
+
+        if difficulty != Difficulty::Hard {
+            // Then until no guessing is needed.
+            loop {
+                let mut state = puzzle.start();
+                match puzzle.deduce(&mut state) {
+                    Progress::Solved => break,
+                    _ => {
+                        let unknown = positions(Board::ALL_CELLS & !(state.ship | state.water));
+                        puzzle.reveal(*unknown.choose(rng).unwrap());
+                    }
+                }
+            }
+        }
+
+        if difficulty == Difficulty::Easy {
+            let mut hidden: Vec<Position> = positions(solution).into_iter()
+                .filter(|pos| !puzzle.givens.iter().any(|(g, _)| g == pos))
+                .collect();
+            hidden.shuffle(rng);
+            for pos in hidden.into_iter().take(2) {
+                puzzle.reveal(pos);
+            }
+        }
+        else {
+            // Take back whatever is not needed.
+            let mut order = puzzle.givens.clone();
+            order.shuffle(rng);
+            for given in order {
+                let mut attempt = puzzle.clone();
+                attempt.givens.retain(|g| *g != given);
+                let needed = match difficulty {
+                    Difficulty::Hard => attempt.solutions(2).len() != 1,
+                    _ => !attempt.is_logical()
+                };
+                if !needed {
+                    puzzle = attempt;
+                }
+            }
+        }
+        puzzle.givens.sort_by_key(|(pos, _)| (pos.y, pos.x));
+        puzzle
+    }
+
+    // A random fleet with no two ships touching.
+    fn random_fleet<R: Rng>(rng: &mut R) -> Mask {
+        loop {
+            let mut board = Board::new();
+            board.setup_with_rng(BoardConfig::Auto, rng).unwrap();
+            let ships: Vec<Mask> = board.ships.iter().map(|s| s.mask).collect();
+            let apart = ships.iter().enumerate()
+                .all(|(i, a)| ships.iter().skip(i+1).all(|b| halo(*a) & b == 0));
+            if apart {
+                return board.occupied
+            }
+        }
+    }
+
+    fn reveal(&mut self, pos: Position) {
+        if !self.givens.iter().any(|(g, _)| *g == pos) {
+            self.givens.push((pos, self.solution & bit(&pos) != 0));
+        }
+    }
+
+    pub fn get_rows(&self) -> [u32; 9] {
+        self.rows
+    }
+
+    pub fn get_cols(&self) -> [u32; 9] {
+        self.cols
+    }
+
+    pub fn get_givens(&self) -> &[(Position, bool)] {
+        &self.givens
+    }
+
+    pub fn get_difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
+
+    pub fn get_solution(&self) -> HashSet<Position> {
+        positions(self.solution).into_iter().collect()
+    }
+
+    pub fn is_given(&self, pos: &Position) -> bool {
+        self.givens.iter().any(|(g, _)| g == pos)
+    }
+
+    // Whether the cells marked as ships are exactly the fleet.
+    pub fn check(&self, ships: &HashSet<Position>) -> bool {
+        ships.iter().fold(0, |m, pos| m | bit(pos)) == self.solution
+    }
+
+    fn start(&self) -> State {
+        let mut state = State{ship: 0, water: 0, placed: Vec::new(), left: Vec::new()};
+        for (pos, ship) in self.givens.iter() {
+            if *ship {state.ship |= bit(pos)} else {state.water |= bit(pos)}
+        }
+        state.left = ShipType::iter()
+            .map(|s| placements(s.get_size() as i32).iter().map(|&m| (m, halo(m))).collect())
+            .collect();
+        state
+    }
+
+    fn settle(state: &mut State, ship: usize, place: (Mask, Mask)) {
+        state.ship |= place.0;
+        state.water |= place.1;
+        state.placed.push(place.0);
+        state.left.remove(ship);
+    }
+
+    // Applies logical deductions until none is left to make.
+    fn deduce(&self, state: &mut State) -> Progress {
+        loop {
+            let before = (state.ship, state.water, state.placed.len());
+
+            // Full rows and columns, and those needing every cell they have left.
+            for i in 1..=9 {
+                for (line, target) in [(row_mask(i), self.rows[i as usize -1]), (col_mask(i), self.cols[i as usize -1])].iter() {
+                    let ships = (state.ship & line).count_ones();
+                    let unknown = line & !state.ship & !state.water;
+                    if ships > *target || ships + unknown.count_ones() < *target {
+                        return Progress::Contradiction
+                    }
+                    if ships == *target {
+                        state.water |= unknown;
+                    }
+                    else if ships + unknown.count_ones() == *target {
+                        state.ship |= unknown;
+                    }
+                }
+            }
+
+            // Ships never touch diagonally.
+            for pos in positions(state.ship) {
+                for (dx, dy) in [(-1,-1), (1,-1), (-1,1), (1,1)].iter() {
+                    let next = Position::new(pos.x + dx, pos.y + dy);
+                    if FogGrid::contains(&next) {
+                        state.water |= bit(&next);
+                    }
+                }
+            }
+            if state.ship & state.water != 0 {
+                return Progress::Contradiction
+            }
+
+            // Places each ship could still go, and cells no ship can reach.
+            let taken = state.placed.iter().fold(0, |m, p| m | p);
+            let (ship, water) = (state.ship, state.water);
+            for places in state.left.iter_mut() {
+                places.retain(|(m, around)| {
+                    m & (water | taken) == 0 && around & ship == 0 && self.fits(ship, *m)
+                });
+            }
+            if state.left.iter().any(|places| places.is_empty()) {
+                return Progress::Contradiction
+            }
+            let reachable = state.left.iter().flatten().fold(taken, |r, (m, _)| r | m);
+            if state.ship & !reachable != 0 {
+                return Progress::Contradiction
+            }
+            state.water |= Board::ALL_CELLS & !reachable;
+
+            // A ship with one place left goes there.
+            if let Some(ship) = state.left.iter().position(|places| places.len() == 1) {
+                let place = state.left[ship][0];
+                Self::settle(state, ship, place);
+            }
+
+            if state.left.is_empty() {
+                let taken = state.placed.iter().fold(0, |m, p| m | p);
+                let counts_match = (1..=9).all(|i| {
+                    (taken & row_mask(i)).count_ones() == self.rows[i as usize -1]
+                        && (taken & col_mask(i)).count_ones() == self.cols[i as usize -1]
+                });
+                if state.ship != taken || !counts_match {
+                    return Progress::Contradiction
+                }
+                return Progress::Solved
+            }
+            if before == (state.ship, state.water, state.placed.len()) {
+                return Progress::Stuck
+            }
+        }
+    }
+
+    // Whether putting a ship on `m` keeps every row and column within its count.
+    fn fits(&self, ship: Mask, m: Mask) -> bool {
+        let with = ship | m;
+        (1..=9).all(|i| {
+            (with & row_mask(i)).count_ones() <= self.rows[i as usize -1]
+                && (with & col_mask(i)).count_ones() <= self.cols[i as usize -1]
+        })
+    }
+
+    // Up to `limit` different solutions.
+    pub fn solutions_upto(&self, limit: usize) -> Vec<HashSet<Position>> {
+        self.solutions(limit).into_iter().map(|s| positions(s).into_iter().collect()).collect()
+    }
+
+    fn solutions(&self, limit: usize) -> Vec<Mask> {
+        let mut found = Vec::new();
+        self.search(self.start(), limit, &mut found);
+        found
+    }
+
+    fn search(&self, mut state: State, limit: usize, found: &mut Vec<Mask>) {
+        match self.deduce(&mut state) {
+            Progress::Contradiction => return,
+            Progress::Solved => {
+                found.push(state.ship);
+                return
+            }
+            Progress::Stuck => {}
+        }
+        // Try every place for the ship with the fewest left.
+        let ship = (0..state.left.len()).min_by_key(|i| state.left[*i].len()).unwrap();
+        for place in state.left[ship].clone() {
+            if found.len() >= limit {
+                return
+            }
+            let mut next = state.clone();
+            Self::settle(&mut next, ship, place);
+            self.search(next, limit, found);
+        }
+    }
+
+    // Whether the puzzle can be solved by deduction alone.
+    pub fn is_logical(&self) -> bool {
+        matches!(self.deduce(&mut self.start()), Progress::Solved)
+    }
+
+    // The puzzle as a grid, with the player's marks: '#' for a ship segment,
+    // '~' for water, and the count of ship segments at the end of each row and
+    // column. Given cells are shown in upper case: 'S' for ship, 'W' for water.
+    pub fn display(&self, ships: &HashSet<Position>, water: &HashSet<Position>) -> String {
+        let mut out = String::from("   A B C D E F G H I");
+        for y in 1..=9 {
+            out.push_str(&format!("\n{}  ", y));
+            for x in 1..=9 {
+                let pos = Position::new(x,y);
+                let c = match self.givens.iter().find(|(g, _)| *g == pos) {
+                    Some((_, true)) => 'S',
+                    Some((_, false)) => 'W',
+                    None if ships.contains(&pos) => '#',
+                    None if water.contains(&pos) => '~',
+                    None => '.'
+                };
+                out.push(c);
+                out.push(' ');
+            }
+            out.push_str(&format!(" {}", self.rows[y as usize -1]));
+        }
+        out.push_str("\n\n   ");
+        for count in self.cols.iter() {
+            out.push_str(&format!("{} ", count));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Puzzle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.display(&HashSet::new(), &HashSet::new()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn generated_puzzles_have_one_solution() {
+        let mut rng = StdRng::seed_from_u64(11);
+        for difficulty in Difficulty::iter() {
+            let puzzle = Puzzle::generate(difficulty, &mut rng);
+            let solutions = puzzle.solutions_upto(2);
+            assert_eq!(solutions.len(), 1);
+            assert_eq!(solutions[0], puzzle.get_solution());
+            assert!(puzzle.check(&puzzle.get_solution()));
+            assert_eq!(puzzle.get_rows().iter().sum::<u32>(), 14);
+            if difficulty != Difficulty::Hard {
+                assert!(puzzle.is_logical());
+            }
+        }
+    }
+
+    #[test]
+    fn deduction_finds_a_fully_clued_fleet() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut puzzle = Puzzle::generate(Difficulty::Hard, &mut rng);
+        for pos in FogGrid::all_positions() {
+            puzzle.reveal(pos);
+        }
+        assert!(puzzle.is_logical());
+        let mut wrong = puzzle.get_solution();
+        wrong.remove(&wrong.iter().next().copied().unwrap());
+        assert!(!puzzle.check(&wrong));
+    }
+}
@@ -0,0 +1,1792 @@
+// Multiplayer side of the game: a lobby shared by all connections, holding
+// every running game, and a session per connection that speaks the line
+// protocol on top of command_parser/command_handler.
+//
+// Besides replying to its own commands, a connection can be pushed
+// unsolicited lines by the lobby. These always start with an upper case
+// keyword:
+//
+//   JOINED <game> <player>          a second player joined a match, sent to them as well
+//   READY <game> <player>           a player has finished placing their fleet
+//   SHOT <game> <player> <cell> <HIT|MISS>
+//   FOG <game> <player> <board>     what is known of <player>'s fleet
+//   REVEAL <game> <player> <board>  <player>'s fleet once the game is over
+//   GAMEOVER <game> <winner>
+//   CHAT <game> <player> <text>
+//   ENDED <game>                    the server stopped the game, nobody won
+//   NOTICE <text>                   a message from the server operator
+//   SAVED <game> <token>            the server is restarting; RESUME <game> <token> once it is back
+//
+// Boards are 9 rows separated by '/', each row reading A to I:
+// '.' unknown, 'o' miss, 'X' hit, and on REVEAL '#' for an untouched ship.
+//
+// HINT is answered with `HINT <cell> <hints left> <heatmap>`, the heatmap
+// being a board where each unknown cell is replaced by a digit 0-9: the
+// chance, in tenths, that a ship is there.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufRead, BufReader, BufWriter, Error, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Board, CommandResult, GameCommand, Position, command_handler, command_parser};
+use crate::accounts::{AccountStore, Credentials};
+use crate::config::Config;
+use crate::layout::{parse_partial_layout, serialize_layout};
+use crate::leaderboard::Leaderboard;
+use crate::limits::{Guard, Limits, TokenBucket};
+use crate::log_event;
+use crate::logging::{self, Opt};
+use crate::metrics::METRICS;
+use crate::snapshot::{self, SavedGame, SavedPlayer, Snapshot};
+use crate::solver::{ProbabilityMap, Solver};
+use crate::stats::{GameTracker, StatsStore};
+use crate::strategy::{CellState, FogGrid};
+use crate::tls::{Stream, TlsStream};
+
+
+pub type ConnId = u32;
+pub type GameId = u32;
+pub type SharedLobby = Arc<Mutex<Lobby>>;
+
+// Longest chat message accepted, in characters.
+pub const MAX_CHAT_LEN: usize = 200;
+
+// Hints a player may ask for in one game, unless the lobby is set up otherwise,
+// and the points each one costs off the final score.
+pub const DEFAULT_HINT_LIMIT: u32 = 3;
+pub const HINT_PENALTY: i32 = 5;
+
+// Finished games whose replay is kept around.
+pub const MAX_REPLAYS: usize = 100;
+
+// Games played over HTTP have no connection to end them: at most this many
+// run at once, and one nobody has fired at for a while is ended.
+pub const MAX_API_GAMES: usize = 200;
+pub const API_GAME_IDLE: Duration = Duration::from_secs(30 * 60);
+
+// How long players of a restored game have to RESUME it before it is ended.
+pub const RESUME_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+// How often reap() looks for games to end.
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+
+pub struct Player {
+    conn: Option<ConnId>,
+    name: Option<String>,  // Statistics are only kept for named players
+    fleet: Option<Board>,  // The fleet this player defends
+    ready: bool,           // Done placing the fleet
+    hints: u32,            // Hints asked for so far
+    tracker: GameTracker,
+    seat: Option<String>,  // Token to take this place back with, in a game restored without its players
+}
+
+impl Player {
+    // A match player, who starts out with an empty board to place ships on.
+    fn new(conn: ConnId, name: Option<String>) -> Player {
+        Player{conn: Some(conn), name, fleet: Some(Board::new()), ready: false, hints: 0, tracker: GameTracker::new(), seat: None}
+    }
+
+    // The computer, defending a fleet it placed itself.
+    fn computer(fleet: Board) -> Player {
+        Player{conn: None, name: None, fleet: Some(fleet), ready: true, hints: 0, tracker: GameTracker::new(), seat: None}
+    }
+}
+
+
+// One shot of a replay. Players are numbered from 1, as in SHOT lines.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ShotRecord {
+    pub player: usize,
+    pub cell: String,
+    pub hit: bool,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Replay {
+    pub id: GameId,
+    pub solo: bool,
+    pub players: Vec<Option<String>>,
+    pub shots: Vec<ShotRecord>,
+    pub winner: Option<usize>,
+    pub fleets: Vec<String>,  // Revealed boards, once the game is over
+}
+
+
+pub struct Game {
+    id: GameId,
+    players: Vec<Player>,
+    spectators: HashSet<ConnId>,
+    turn: usize,
+    solo: bool,
+    shots: Vec<ShotRecord>,
+    token: Option<String>,  // Games created over HTTP are played by whoever holds it
+    created: Instant,
+    last_shot: Instant,     // Or when the game was set up, before the first shot
+}
+
+impl Game {
+    // A single player firing at a computer placed fleet.
+    fn new_solo(id: GameId, conn: ConnId, name: Option<String>, fleet: Board) -> Game {
+        let human = Player{fleet: None, ready: true, ..Player::new(conn, name)};
+        let players = vec![human, Player::computer(fleet)];
+        Game{id, players, spectators: HashSet::new(), turn: 0, solo: true, shots: Vec::new(), token: None, created: Instant::now(), last_shot: Instant::now()}
+    }
+
+    // A single player game driven over HTTP: the player has no connection, only the token.
+    fn new_api(id: GameId, fleet: Board, token: String) -> Game {
+        let human = Player{conn: None, name: None, fleet: None, ready: true, hints: 0, tracker: GameTracker::new(), seat: None};
+        let players = vec![human, Player::computer(fleet)];
+        Game{id, players, spectators: HashSet::new(), turn: 0, solo: true, shots: Vec::new(), token: Some(token), created: Instant::now(), last_shot: Instant::now()}
+    }
+
+    // A match waiting for a second player to join.
+    fn new_match(id: GameId, conn: ConnId, name: Option<String>) -> Game {
+        let players = vec![Player::new(conn, name)];
+        Game{id, players, spectators: HashSet::new(), turn: 0, solo: false, shots: Vec::new(), token: None, created: Instant::now(), last_shot: Instant::now()}
+    }
+
+    pub fn get_id(&self) -> GameId {
+        self.id
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.players.len() == 2
+    }
+
+    // Both fleets are in place and shots can be fired.
+    pub fn is_underway(&self) -> bool {
+        self.is_started() && self.players.iter().all(|p| p.ready)
+    }
+
+    pub fn is_solo(&self) -> bool {
+        self.solo
+    }
+
+    // "solo", "match" or, for single player games driven over HTTP, "http".
+    pub fn kind(&self) -> &'static str {
+        if self.token.is_some() {"http"} else if self.solo {"solo"} else {"match"}
+    }
+
+    // Side whose turn it is, numbered from 1.
+    pub fn get_turn(&self) -> usize {
+        self.turn + 1
+    }
+
+    pub fn get_player_names(&self) -> Vec<Option<String>> {
+        self.players.iter().map(|p| p.name.clone()).collect()
+    }
+
+    pub fn get_spectator_count(&self) -> usize {
+        self.spectators.len()
+    }
+
+    // What is known of a side's fleet (numbered from 1), in the same form as FOG lines.
+    pub fn get_fog(&self, side: usize) -> Option<String> {
+        let fleet = self.players.get(side.checked_sub(1)?)?.fleet.as_ref()?;
+        Some(render_board(fleet, false))
+    }
+
+    pub fn check_token(&self, token: &str) -> bool {
+        self.token.as_deref() == Some(token)
+    }
+
+    // Fleets are only shown once there is a winner.
+    pub fn get_replay(&self, winner: Option<usize>) -> Replay {
+        let fleets = match winner {
+            Some(_) => self.players.iter().filter_map(|p| p.fleet.as_ref()).map(|f| render_board(f, true)).collect(),
+            None => Vec::new()
+        };
+        Replay{id: self.id, solo: self.solo, players: self.get_player_names(), shots: self.shots.clone(),
+               winner: winner.map(|w| w + 1), fleets}
+    }
+
+    fn side_of(&self, conn: ConnId) -> Option<usize> {
+        self.players.iter().position(|p| p.conn == Some(conn))
+    }
+
+    // Everybody following the game, optionally leaving one connection out.
+    fn audience(&self, except: Option<ConnId>) -> Vec<ConnId> {
+        self.players.iter().filter_map(|p| p.conn)
+            .chain(self.spectators.iter().copied())
+            .filter(|c| Some(*c) != except)
+            .collect()
+    }
+
+    // FOG (or REVEAL) line for one side's fleet, if that side has one.
+    fn board_line(&self, side: usize, reveal: bool) -> Option<String> {
+        let fleet = self.players.get(side)?.fleet.as_ref()?;
+        let kind = if reveal {"REVEAL"} else {"FOG"};
+        Some(format!("{} {} {} {}", kind, self.id, side+1, render_board(fleet, reveal)))
+    }
+
+    fn board_lines(&self, reveal: bool) -> Vec<String> {
+        (0..self.players.len()).filter_map(|side| self.board_line(side, reveal)).collect()
+    }
+
+    // Connected players are given a seat token, players who never came back keep theirs.
+    fn save(&self) -> SavedGame {
+        let players = self.players.iter().map(|p| SavedPlayer{
+            name: p.name.clone(),
+            seat: p.seat.clone().or_else(|| p.conn.map(|_| new_token())),
+            fleet: p.fleet.as_ref().map(serialize_layout),
+            ready: p.ready,
+            hints: p.hints,
+        }).collect();
+        SavedGame{id: self.id, solo: self.solo, turn: self.turn, token: self.token.clone(), players, shots: self.shots.clone()}
+    }
+
+    // Whether a player of a restored game has yet to RESUME it.
+    fn is_awaiting_resume(&self) -> bool {
+        self.players.iter().any(|p| p.seat.is_some())
+    }
+
+    // Places the fleets and fires the shots again. Nobody is connected until they RESUME.
+    fn restore(saved: SavedGame) -> Result<Game, String> {
+        let id = saved.id;
+        let mut players = Vec::new();
+        for p in saved.players {
+            let fleet = match p.fleet {
+                Some(text) => {
+                    let mut board = Board::new();
+                    board.setup(parse_partial_layout(&text)?).map_err(|e| format!("game {}: {}", id, e))?;
+                    Some(board)
+                }
+                None => None
+            };
+            players.push(Player{conn: None, name: p.name, fleet, ready: p.ready, hints: p.hints, tracker: GameTracker::new(), seat: p.seat});
+        }
+        if players.is_empty() || players.len() > 2 || saved.turn >= players.len() {
+            return Err(format!("game {}: wrong number of players", id))
+        }
+        let mut game = Game{id, players, spectators: HashSet::new(), turn: saved.turn, solo: saved.solo, shots: Vec::new(),
+                            token: saved.token, created: Instant::now(), last_shot: Instant::now()};
+        for shot in saved.shots {
+            let side = shot.player.wrapping_sub(1);
+            let pos = Position::from_coord(&shot.cell);
+            let fleet = game.players.get_mut(1usize.wrapping_sub(side)).and_then(|p| p.fleet.as_mut());
+            let (fleet, pos) = match (side, fleet, pos) {
+                (0 | 1, Some(fleet), Some(pos)) => (fleet, pos),
+                _ => return Err(format!("game {}: bad shot {} {}", id, shot.player, shot.cell))
+            };
+            let targeting = fleet.has_damaged_ship();
+            let hit = fleet.hit_cell(pos);
+            game.players[side].tracker.record(targeting, hit);
+            game.shots.push(ShotRecord{hit, ..shot});
+        }
+        Ok(game)
+    }
+}
+
+
+fn new_token() -> String {
+    (0..16).map(|_| format!("{:02x}", rand::random::<u8>())).collect()
+}
+
+
+fn render_board(board: &Board, reveal: bool) -> String {
+    let fog = board.get_fog_of_war();
+    let contents = board.get_contents();
+    let mut rows = Vec::new();
+    for y in 0..9 {
+        let mut row = String::new();
+        for x in 0..9 {
+            if reveal && fog[x][y] == '.' && contents[x][y] == '1' {
+                row.push('#');
+            }
+            else {
+                row.push(fog[x][y]);
+            }
+        }
+        rows.push(row);
+    }
+    rows.join("/")
+}
+
+
+fn render_heatmap(fog: &FogGrid, map: &ProbabilityMap) -> String {
+    let mut rows = Vec::new();
+    for y in 1..=9 {
+        let row: String = (1..=9).map(|x| {
+            let pos = Position::new(x,y);
+            match fog.get(&pos) {
+                CellState::Hit => 'X',
+                CellState::Miss => 'o',
+                CellState::Unknown => {
+                    let tenths = ((map.get(&pos) * 10.0) as u32).min(9);
+                    std::char::from_digit(tenths, 10).unwrap()
+                }
+            }
+        }).collect();
+        rows.push(row);
+    }
+    rows.join("/")
+}
+
+
+// Lets the lobby cut a connection short, for KICK and SHUTDOWN.
+pub struct ClientHandle {
+    peer: SocketAddr,
+    close: Box<dyn Fn() + Send>,  // Stops reading, so the connection winds down as if the client left
+}
+
+impl ClientHandle {
+    pub fn new(peer: SocketAddr, close: impl Fn() + Send + 'static) -> ClientHandle {
+        ClientHandle{peer, close: Box::new(close)}
+    }
+}
+
+
+// A connection counted against its address's limit until dropped.
+pub struct Admission {
+    lobby: SharedLobby,
+    ip: IpAddr,
+}
+
+impl Admission {
+    // Fails with the reason to give the client when its address is banned or has too many connections.
+    pub fn new(lobby: &SharedLobby, ip: IpAddr) -> Result<Admission, String> {
+        let mut guard = lobby.lock().unwrap();
+        if guard.closing {
+            return Err(String::from("Server shutting down."))
+        }
+        let limits = guard.limits;
+        guard.guard.admit(ip, &limits)?;
+        Ok(Admission{lobby: lobby.clone(), ip})
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        self.lobby.lock().unwrap().guard.release(self.ip);
+    }
+}
+
+
+pub struct Lobby {
+    next_conn: ConnId,
+    next_game: GameId,
+    clients: HashMap<ConnId, Sender<String>>,
+    games: HashMap<GameId, Game>,
+    hint_limit: u32,
+    stats: StatsStore,
+    leaderboard: Leaderboard,
+    accounts: Arc<Mutex<AccountStore>>,  // Locked on its own, as checking a password takes a while
+    identities: HashMap<ConnId, String>,  // Who is logged in on each connection
+    replays: VecDeque<Replay>,  // Most recently finished games last
+    handles: HashMap<ConnId, ClientHandle>,
+    limits: Limits,
+    guard: Guard,
+    snapshot: Option<PathBuf>,  // Where games are saved on shutdown; None ends them
+    closing: bool,              // Shutting down, new connections are turned away
+}
+
+impl Default for Lobby {
+    fn default() -> Self {
+        Lobby{next_conn: 0, next_game: 0, clients: HashMap::new(), games: HashMap::new(),
+              hint_limit: DEFAULT_HINT_LIMIT, stats: StatsStore::in_memory(), leaderboard: Leaderboard::in_memory(),
+              accounts: Arc::new(Mutex::new(AccountStore::in_memory())), identities: HashMap::new(),
+              replays: VecDeque::new(), handles: HashMap::new(), limits: Limits::default(), guard: Guard::default(),
+              snapshot: None, closing: false}
+    }
+}
+
+impl Lobby {
+    pub fn new() -> Lobby {
+        Lobby::default()
+    }
+
+    pub fn shared() -> SharedLobby {
+        Arc::new(Mutex::new(Lobby::new()))
+    }
+
+    pub fn set_hint_limit(&mut self, limit: u32) {
+        self.hint_limit = limit;
+    }
+
+    pub fn apply_config(&mut self, config: &Config) {
+        self.hint_limit = config.hints;
+        self.limits = config.limits;
+        logging::set_level(config.log_level);
+    }
+
+    pub fn get_limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn set_stats(&mut self, stats: StatsStore) {
+        self.stats = stats;
+    }
+
+    pub fn get_stats(&self) -> &StatsStore {
+        &self.stats
+    }
+
+    pub fn set_leaderboard(&mut self, leaderboard: Leaderboard) {
+        self.leaderboard = leaderboard;
+    }
+
+    pub fn get_leaderboard(&self) -> &Leaderboard {
+        &self.leaderboard
+    }
+
+    pub fn set_accounts(&mut self, accounts: AccountStore) {
+        self.accounts = Arc::new(Mutex::new(accounts));
+    }
+
+    pub fn set_snapshot(&mut self, path: &Path) {
+        self.snapshot = Some(path.to_path_buf());
+    }
+
+    // Puts back games saved by an earlier run, returning how many.
+    pub fn restore_games(&mut self, snapshot: Snapshot) -> usize {
+        self.next_game = self.next_game.max(snapshot.next_game);
+        let mut restored = 0;
+        for saved in snapshot.games {
+            let id = saved.id;
+            if self.games.contains_key(&id) {
+                log_event!(Warn, "restore_failed", game = id, error = "id taken");
+                continue
+            }
+            match Game::restore(saved) {
+                Ok(game) => {
+                    log_event!(Info, "game_restored", game = id, kind = game.kind(), shots = game.shots.len());
+                    self.games.insert(id, game);
+                    restored += 1;
+                }
+                Err(e) => log_event!(Warn, "restore_failed", game = id, error = e)
+            }
+        }
+        restored
+    }
+
+    // Writes every game to the snapshot file, then takes them out of the lobby,
+    // telling each player how to come back. Games stay put if the file cannot be written.
+    // With no games to save, the file left from the last run is removed.
+    fn save_games(&mut self, path: &Path) -> Result<usize, String> {
+        let mut ids: Vec<GameId> = self.games.keys().copied().collect();
+        ids.sort_unstable();
+        let games: Vec<SavedGame> = ids.iter().map(|id| self.games[id].save()).collect();
+        if games.is_empty() {
+            snapshot::remove(path)?;
+            return Ok(0)
+        }
+        snapshot::save(path, &Snapshot{next_game: self.next_game, games: games.clone()})?;
+        for saved in games.iter() {
+            let game = self.games.remove(&saved.id).unwrap();
+            for (player, seat) in game.players.iter().zip(saved.players.iter()) {
+                if let (Some(conn), Some(seat)) = (player.conn, &seat.seat) {
+                    self.send(conn, &format!("SAVED {} {}", saved.id, seat));
+                }
+            }
+        }
+        Ok(games.len())
+    }
+
+    pub fn connect(&mut self, outbox: Sender<String>) -> ConnId {
+        self.next_conn += 1;
+        self.clients.insert(self.next_conn, outbox);
+        self.next_conn
+    }
+
+    pub fn disconnect(&mut self, conn: ConnId) {
+        for game in self.games.values_mut() {
+            game.spectators.remove(&conn);
+        }
+        self.clients.remove(&conn);
+        self.identities.remove(&conn);
+        self.handles.remove(&conn);
+    }
+
+    pub fn set_handle(&mut self, conn: ConnId, handle: ClientHandle) {
+        self.handles.insert(conn, handle);
+    }
+
+    // Keeps the client's address out for a while. New connections only: the client is left to close its own.
+    pub fn ban(&mut self, conn: ConnId) {
+        if let Some(handle) = self.handles.get(&conn) {
+            let length = Duration::from_secs(self.limits.ban_seconds);
+            self.guard.ban(handle.peer.ip(), length);
+        }
+    }
+
+    // Disconnects a client, telling it why first. Its game is left as if it had quit.
+    pub fn kick(&mut self, conn: ConnId, reason: &str) -> bool {
+        if !self.clients.contains_key(&conn) {
+            return false
+        }
+        self.send(conn, &format!("NOTICE {}", reason));
+        if let Some(handle) = self.handles.get(&conn) {
+            (handle.close)();
+        }
+        true
+    }
+
+    // Stops a game without a winner. Nothing is recorded but the replay.
+    pub fn end_game(&mut self, id: GameId) -> bool {
+        let game = match self.games.remove(&id) {
+            Some(game) => game,
+            None => return false
+        };
+        log_event!(Info, "game_ended", game = id, shots = game.shots.len());
+        self.push_replay(game.get_replay(None));
+        self.send_all(&game.audience(None), &format!("ENDED {}", id));
+        true
+    }
+
+    pub fn broadcast(&self, text: &str) {
+        for conn in self.clients.keys() {
+            self.send(*conn, &format!("NOTICE {}", text));
+        }
+    }
+
+    pub fn get_client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    // One line per game for the admin console:
+    // id, kind, state, players as <conn>:<name> and number of spectators.
+    // Players yet to RESUME a restored game show as "away".
+    pub fn games_report(&self) -> String {
+        let games = self.get_games();
+        let mut lines = vec![format!("GAMES {}", games.len())];
+        for game in games {
+            let kind = game.kind();
+            let state = if !game.is_started() {"waiting"} else if !game.is_underway() {"placing"} else {"underway"};
+            let players: Vec<String> = game.players.iter().map(|p| match p.conn {
+                Some(conn) => format!("{}:{}", conn, p.name.as_deref().unwrap_or("-")),
+                None if p.seat.is_some() => String::from("away"),
+                None if p.fleet.is_none() => String::from("http"),
+                None => String::from("computer")
+            }).collect();
+            lines.push(format!("{} {} {} {} spectators={} shots={}",
+                               game.id, kind, state, players.join(","), game.spectators.len(), game.shots.len()));
+        }
+        lines.join("\n")
+    }
+
+    // One line per connection: id, address, account name and game.
+    pub fn clients_report(&self) -> String {
+        let mut conns: Vec<ConnId> = self.clients.keys().copied().collect();
+        conns.sort_unstable();
+        let mut lines = vec![format!("CLIENTS {}", conns.len())];
+        for conn in conns {
+            let peer = self.handles.get(&conn).map_or(String::from("-"), |h| h.peer.to_string());
+            let name = self.identities.get(&conn).map_or("-", String::as_str);
+            let game = self.games.values().find(|g| g.side_of(conn).is_some() || g.spectators.contains(&conn));
+            let game = game.map_or(String::from("-"), |g| g.id.to_string());
+            lines.push(format!("{} {} {} {}", conn, peer, name, game));
+        }
+        lines.join("\n")
+    }
+
+    pub fn get_game(&self, id: GameId) -> Option<&Game> {
+        self.games.get(&id)
+    }
+
+    // Running games, oldest first.
+    pub fn get_games(&self) -> Vec<&Game> {
+        let mut games: Vec<&Game> = self.games.values().collect();
+        games.sort_by_key(|g| g.id);
+        games
+    }
+
+    // Replay of a running game so far, or of a recently finished one.
+    pub fn get_replay(&self, id: GameId) -> Option<Replay> {
+        match self.games.get(&id) {
+            Some(game) => Some(game.get_replay(None)),
+            None => self.replays.iter().find(|r| r.id == id).cloned()
+        }
+    }
+
+    // Starts a single player game to be played over HTTP, returning its id and token.
+    pub fn add_api_game(&mut self, fleet: Board) -> Result<(GameId, String), String> {
+        self.expire_idle_games(Instant::now());
+        if self.games.values().filter(|g| g.token.is_some()).count() >= MAX_API_GAMES {
+            return Err(String::from("Too many games in progress, try again later."))
+        }
+        let token = new_token();
+        let id = self.add_game(|id| Game::new_api(id, fleet, token.clone()));
+        Ok((id, token))
+    }
+
+    // Ends, without a winner, the HTTP games nobody has fired at for API_GAME_IDLE
+    // and the restored games still missing a player after RESUME_WINDOW.
+    pub fn expire_idle_games(&mut self, now: Instant) {
+        let idle: Vec<GameId> = self.games.values()
+            .filter(|g| (g.token.is_some() && now.saturating_duration_since(g.last_shot) > API_GAME_IDLE)
+                        || (g.is_awaiting_resume() && now.saturating_duration_since(g.created) > RESUME_WINDOW))
+            .map(|g| g.id)
+            .collect();
+        for id in idle {
+            log_event!(Info, "game_expired", game = id);
+            self.end_game(id);
+        }
+    }
+
+    pub fn send(&self, conn: ConnId, msg: &str) {
+        if let Some(outbox) = self.clients.get(&conn) {
+            // A closed outbox just means the client is going away.
+            outbox.send(msg.to_string()).ok();
+        }
+    }
+
+    fn send_all(&self, conns: &[ConnId], msg: &str) {
+        for conn in conns {
+            self.send(*conn, msg);
+        }
+    }
+
+    fn add_game(&mut self, make: impl FnOnce(GameId) -> Game) -> GameId {
+        self.next_game += 1;
+        let game = make(self.next_game);
+        METRICS.game_started();
+        log_event!(Info, "game_started", game = game.id, kind = game.kind());
+        self.games.insert(game.id, game);
+        self.next_game
+    }
+
+    // Ends a game, telling everybody but `except` who won.
+    // Spectators also get to see both fleets.
+    fn finish_game(&mut self, id: GameId, winner: usize, except: Option<ConnId>) {
+        if let Some(game) = self.games.remove(&id) {
+            METRICS.game_finished(game.created.elapsed());
+            log_event!(Info, "game_finished", game = id, winner = winner+1, shots = game.shots.len(),
+                       duration_s = game.created.elapsed().as_secs());
+            self.record_stats(&game, winner);
+            self.push_replay(game.get_replay(Some(winner)));
+            let spectators: Vec<ConnId> = game.spectators.iter().copied().collect();
+            for line in game.board_lines(true) {
+                self.send_all(&spectators, &line);
+            }
+            self.send_all(&game.audience(except), &format!("GAMEOVER {} {}", id, winner+1));
+        }
+    }
+
+    fn push_replay(&mut self, replay: Replay) {
+        if self.replays.len() == MAX_REPLAYS {
+            self.replays.pop_front();
+        }
+        self.replays.push_back(replay);
+    }
+
+    fn record_stats(&mut self, game: &Game, winner: usize) {
+        // Only games where shots have been fired count: walking out of a match
+        // nobody has played yet would otherwise hand out free wins and points.
+        if !game.is_underway() || game.shots.is_empty() {
+            return
+        }
+        for (side, player) in game.players.iter().enumerate() {
+            let name = match &player.name {
+                Some(name) => name,
+                None => continue
+            };
+            let opponent = match game.players.get(1 - side) {
+                Some(_) if game.solo => "computer",
+                Some(p) => p.name.as_deref().unwrap_or("anonymous"),
+                None => continue
+            };
+            let stats = player.tracker.finish(name, opponent, side == winner, player.hints);
+            if let Err(e) = self.stats.record(stats) {
+                log_event!(Error, "stats_failed", game = game.id, error = e);
+            }
+        }
+        // Only matches between two named players count towards the ladder.
+        if let (false, [a, b]) = (game.solo, game.players.as_slice()) {
+            let (winner, loser) = if winner == 0 {(a, b)} else {(b, a)};
+            let (winner, loser) = match (&winner.name, &loser.name) {
+                (Some(winner), Some(loser)) => (winner, loser),
+                _ => return
+            };
+            if let Err(e) = self.leaderboard.record(winner, loser) {
+                log_event!(Error, "leaderboard_failed", game = game.id, error = e);
+            }
+        }
+    }
+
+    // Takes a player out of a game. Walking out of a running match hands the win to the opponent.
+    fn leave_game(&mut self, id: GameId, conn: ConnId) {
+        let (side, started) = match self.games.get(&id) {
+            Some(game) => match game.side_of(conn) {
+                Some(side) => (side, game.is_started()),
+                None => return
+            },
+            None => return
+        };
+        // Nobody wins a restored game the other side never came back to.
+        let away = self.games.get(&id).is_some_and(|game| game.players.get(1 - side).is_some_and(|p| p.seat.is_some()));
+        if started && !away {
+            self.finish_game(id, 1 - side, Some(conn));
+        }
+        else if away {
+            self.end_game(id);
+        }
+        else {
+            self.games.remove(&id);
+        }
+    }
+
+
+    // `side` (counted from 0) fires at the other side of game `id`. Everybody
+    // following the game but `except` is told about the shot.
+    pub fn fire(&mut self, id: GameId, side: usize, x: i32, y: i32, except: Option<ConnId>) -> CommandResult {
+        let game = match self.games.get_mut(&id) {
+            Some(game) => game,
+            None => return CommandResult::Failure(String::from("No such game."))
+        };
+        if !game.is_started() {
+            return CommandResult::Failure(String::from("Waiting for opponent."))
+        }
+        if !game.is_underway() {
+            return CommandResult::Failure(String::from("Waiting for fleets to be placed."))
+        }
+        if game.turn != side {
+            return CommandResult::Failure(String::from("Not your turn."))
+        }
+        let target = 1 - side;
+        let pos = Position::new(x,y);
+        let on_board = game.players[target].fleet.as_ref().is_some_and(|b| b.is_valid_position(&pos));
+        // A solo game answers MISS as it always has, but in a match a stray shot must not cost the turn.
+        if !game.solo && !on_board {
+            return CommandResult::Failure(String::from("Not on the board."))
+        }
+        let targeting = game.players[target].fleet.as_ref().is_some_and(|b| b.has_damaged_ship());
+        let result = match command_handler(&mut game.players[target].fleet, GameCommand::Cell(x,y)) {
+            CommandResult::GameComplete(score) => CommandResult::GameComplete(score - HINT_PENALTY * game.players[side].hints as i32),
+            result => result
+        };
+        let outcome = match result {
+            CommandResult::Success(_) | CommandResult::GameComplete(_) => "HIT",
+            _ => "MISS"
+        };
+        if !game.solo {
+            game.turn = target;
+        }
+
+        let audience = game.audience(except);
+        let mut lines = Vec::new();
+        if on_board {
+            game.last_shot = Instant::now();
+            game.players[side].tracker.record(targeting, outcome == "HIT");
+            game.shots.push(ShotRecord{player: side+1, cell: pos.to_string(), hit: outcome == "HIT"});
+            lines.push(format!("SHOT {} {} {} {}", id, side+1, pos, outcome));
+            lines.extend(game.board_line(target, false));
+        }
+        for line in lines {
+            self.send_all(&audience, &line);
+        }
+        if let CommandResult::GameComplete(_) = result {
+            self.finish_game(id, side, except);
+        }
+        result
+    }
+}
+
+
+pub struct Session {
+    conn: ConnId,
+    lobby: SharedLobby,
+    game: Option<GameId>,
+    watching: Option<GameId>,
+    name: Option<String>,
+    commands: TokenBucket,
+    invalid: TokenBucket,  // Invalid commands; running out gets the address banned
+}
+
+impl Session {
+    pub fn new(lobby: SharedLobby, outbox: Sender<String>) -> Session {
+        let (conn, limits) = {
+            let mut lobby = lobby.lock().unwrap();
+            (lobby.connect(outbox), lobby.limits)
+        };
+        Session{conn, lobby, game: None, watching: None, name: None,
+                commands: TokenBucket::new(limits.command_burst), invalid: TokenBucket::new(limits.invalid_commands_per_minute)}
+    }
+
+    pub fn get_conn(&self) -> ConnId {
+        self.conn
+    }
+
+    pub(crate) fn reply(&self, msg: &str) {
+        log_event!(Debug, "reply", conn = self.conn, text = msg);
+        self.lobby.lock().unwrap().send(self.conn, msg);
+    }
+
+    // Handles one line from the client. Returns false once the connection should be closed.
+    pub fn handle_line(&mut self, line: &str) -> bool {
+        let started = Instant::now();
+        let limits = self.lobby.lock().unwrap().limits;
+        if !self.commands.take(limits.commands_per_second, limits.command_burst) {
+            METRICS.rate_limited();
+            log_event!(Debug, "rate_limited", conn = self.conn);
+            self.reply("Too many commands, slow down.");
+            return true
+        }
+        let cmd = command_parser(line);
+        let parsed = cmd != GameCommand::InvalidCommand;
+        METRICS.command(parsed);
+        if !parsed && !self.invalid.take(limits.invalid_commands_per_minute / 60.0, limits.invalid_commands_per_minute) {
+            METRICS.banned();
+            self.lobby.lock().unwrap().ban(self.conn);
+            log_event!(Warn, "banned", conn = self.conn, seconds = limits.ban_seconds);
+            self.reply(&format!("Banned for {} seconds: too many invalid commands.", limits.ban_seconds));
+            return false
+        }
+        let game = self.game.or(self.watching);
+        let result = self.dispatch(cmd);
+        let outcome = match &result {
+            _ if !parsed => "invalid",
+            Some(CommandResult::Success(_)) => "success",
+            Some(CommandResult::Failure(_)) => "failure",
+            Some(CommandResult::Message(_)) | None => "message",
+            Some(CommandResult::Some(_)) => "new_game",
+            Some(CommandResult::None) => "none",
+            Some(CommandResult::GameComplete(_)) => "game_complete",
+            Some(CommandResult::Quit) => "quit",
+        };
+        let open = match result {
+            Some(result) => self.respond(result),
+            None => true
+        };
+        log_event!(Info, "command", conn = self.conn, game = Opt(self.game.or(self.watching).or(game)), cmd = redact(line),
+                   result = outcome, latency_us = started.elapsed().as_micros());
+        open
+    }
+
+    // None when the command has already been answered.
+    fn dispatch(&mut self, cmd: GameCommand) -> Option<CommandResult> {
+        let result = match cmd {
+            GameCommand::NewMatch => self.new_match(),
+            GameCommand::Join(id) => {
+                self.join(id);
+                return None
+            },
+            GameCommand::Watch(id) => {
+                self.watch(id);
+                return None
+            },
+            GameCommand::Resume(id, token) => {
+                self.resume(id, &token);
+                return None
+            },
+            GameCommand::Cell(x,y) => self.fire(x, y),
+            GameCommand::Say(text) => self.say(&text),
+            GameCommand::Hint => self.hint(),
+            GameCommand::Register(name, password) => self.login(name, &password, true),
+            GameCommand::Login(name, password) => self.login(name, &password, false),
+            GameCommand::Stats(name) => self.stats(name),
+            GameCommand::Leaderboard(n) => CommandResult::Message(self.lobby.lock().unwrap().leaderboard.report(n)),
+            GameCommand::Place(..) | GameCommand::Auto | GameCommand::Ready => self.place(cmd),
+            _ => command_handler(&mut None, cmd)
+        };
+        Some(result)
+    }
+
+    fn respond(&mut self, result: CommandResult) -> bool {
+        match result {
+            CommandResult::Success(msg) | CommandResult::Failure(msg) | CommandResult::Message(msg) => {
+                self.reply(&msg);
+            },
+            CommandResult::Some(b) => {
+                self.leave();
+                let (conn, name) = (self.conn, self.name.clone());
+                let id = self.lobby.lock().unwrap().add_game(|id| Game::new_solo(id, conn, name, b));
+                self.game = Some(id);
+                self.reply(&format!("Starting new game. Game ID: {}", id));
+            },
+            CommandResult::None => {
+                self.reply("Nothing to do");
+            },
+            CommandResult::GameComplete(score) => {
+                self.reply(&format!("Game successcully completed. Score {}", score));
+                return false;
+            },
+            CommandResult::Quit => {
+                return false;
+            },
+        }
+        true
+    }
+
+    fn new_match(&mut self) -> CommandResult {
+        self.leave();
+        let (conn, name) = (self.conn, self.name.clone());
+        let id = self.lobby.lock().unwrap().add_game(|id| Game::new_match(id, conn, name));
+        self.game = Some(id);
+        CommandResult::Message(format!("Match {} created. Waiting for opponent.", id))
+    }
+
+    // Replies directly, so the JOINED line comes after the reply.
+    fn join(&mut self, id: GameId) {
+        if self.game == Some(id) {
+            return self.reply("Already in this game.")
+        }
+        let refused = match self.lobby.lock().unwrap().games.get(&id) {
+            None => Some("No such game."),
+            Some(game) if game.solo || game.is_started() => Some("Game already full."),
+            Some(_) => None
+        };
+        if let Some(msg) = refused {
+            return self.reply(msg)
+        }
+        self.leave();
+        let mut lobby = self.lobby.lock().unwrap();
+        let conn = self.conn;
+        let game = match lobby.games.get_mut(&id) {
+            Some(game) => game,
+            None => return lobby.send(conn, "No such game.")
+        };
+        // Someone else may have joined while the lock was released.
+        if game.solo || game.is_started() {
+            return lobby.send(conn, "Game already full.")
+        }
+        game.players.push(Player::new(conn, self.name.clone()));
+        let audience = game.audience(None);
+        let spectators: Vec<ConnId> = game.spectators.iter().copied().collect();
+        let lines = game.board_lines(false);
+        lobby.send(conn, &format!("Joined match {}. You are player 2.", id));
+        lobby.send_all(&audience, &format!("JOINED {} 2", id));
+        for line in lines {
+            lobby.send_all(&spectators, &line);
+        }
+        self.game = Some(id);
+    }
+
+    // Takes back a seat in a restored game. Replies directly, then catches the player up.
+    fn resume(&mut self, id: GameId, token: &str) {
+        if self.game == Some(id) {
+            return self.reply("Already in this game.")
+        }
+        let found = match self.lobby.lock().unwrap().games.get(&id) {
+            None => Err("No such game."),
+            Some(game) => game.players.iter().position(|p| p.seat.as_deref() == Some(token)).ok_or("Wrong token for this game.")
+        };
+        if let Err(msg) = found {
+            return self.reply(msg)
+        }
+        self.leave();
+        let mut lobby = self.lobby.lock().unwrap();
+        let conn = self.conn;
+        let game = match lobby.games.get_mut(&id) {
+            Some(game) => game,
+            None => return lobby.send(conn, "No such game.")
+        };
+        let side = match game.players.iter().position(|p| p.seat.as_deref() == Some(token)) {
+            Some(side) => side,
+            None => return lobby.send(conn, "Wrong token for this game.")
+        };
+        game.players[side].conn = Some(conn);
+        game.players[side].seat = None;
+        game.spectators.remove(&conn);
+        let audience = game.audience(Some(conn));
+        let lines = game.board_lines(false);
+        let turn = if game.is_underway() {format!(" Player {} to fire.", game.get_turn())} else {String::new()};
+        lobby.send(conn, &format!("Resumed game {}. You are player {}.{}", id, side+1, turn));
+        for line in lines {
+            lobby.send(conn, &line);
+        }
+        lobby.send_all(&audience, &format!("JOINED {} {}", id, side+1));
+        self.game = Some(id);
+        self.watching = self.watching.filter(|w| *w != id);
+    }
+
+    // Replies directly, as the spectator must be caught up before any further events arrive.
+    fn watch(&mut self, id: GameId) {
+        let mut lobby = self.lobby.lock().unwrap();
+        let game = match lobby.games.get_mut(&id) {
+            Some(game) => game,
+            None => return lobby.send(self.conn, "No such game.")
+        };
+        if game.side_of(self.conn).is_some() {
+            return lobby.send(self.conn, "Cannot watch your own game.")
+        }
+        game.spectators.insert(self.conn);
+        let lines = game.board_lines(false);
+        if let Some(old) = self.watching.filter(|old| *old != id) {
+            if let Some(game) = lobby.games.get_mut(&old) {
+                game.spectators.remove(&self.conn);
+            }
+        }
+        self.watching = Some(id);
+        lobby.send(self.conn, &format!("Watching game {}.", id));
+        for line in lines {
+            lobby.send(self.conn, &line);
+        }
+    }
+
+    fn fire(&mut self, x: i32, y: i32) -> CommandResult {
+        let mut lobby = self.lobby.lock().unwrap();
+        let game = match self.game.and_then(|id| lobby.games.get_mut(&id)) {
+            Some(game) => game,
+            None => {
+                if self.watching.is_some() {
+                    return CommandResult::Failure(String::from("Spectators cannot fire."))
+                }
+                return command_handler(&mut None, GameCommand::Cell(x,y))
+            }
+        };
+        let side = match game.side_of(self.conn) {
+            Some(side) => side,
+            None => return command_handler(&mut None, GameCommand::Cell(x,y))
+        };
+        let id = game.id;
+        let result = lobby.fire(id, side, x, y, Some(self.conn));
+        if let CommandResult::GameComplete(_) = result {
+            self.game = None;
+        }
+        result
+    }
+
+    // PLACE, AUTO and READY all work on the player's own fleet until it is declared ready.
+    fn place(&mut self, cmd: GameCommand) -> CommandResult {
+        let mut lobby = self.lobby.lock().unwrap();
+        let game = match self.game.and_then(|id| lobby.games.get_mut(&id)) {
+            Some(game) if !game.solo => game,
+            _ => return CommandResult::Failure(String::from("Not in a match."))
+        };
+        let side = match game.side_of(self.conn) {
+            Some(side) => side,
+            None => return CommandResult::Failure(String::from("Not in a match."))
+        };
+        if game.players[side].ready {
+            return CommandResult::Failure(String::from("Fleet already in place."))
+        }
+        let ready = cmd == GameCommand::Ready;
+        let result = command_handler(&mut game.players[side].fleet, cmd);
+        if ready {
+            if let CommandResult::Success(_) = result {
+                game.players[side].ready = true;
+                if game.is_underway() {
+                    for player in game.players.iter_mut() {
+                        player.tracker.restart();
+                    }
+                }
+                let line = format!("READY {} {}", game.id, side+1);
+                let audience = game.audience(Some(self.conn));
+                lobby.send_all(&audience, &line);
+            }
+        }
+        result
+    }
+
+    fn say(&mut self, text: &str) -> CommandResult {
+        let text = sanitize_chat(text);
+        if text.is_empty() {
+            return CommandResult::Failure(String::from("Empty message."))
+        }
+        if text.chars().count() > MAX_CHAT_LEN {
+            return CommandResult::Failure(format!("Message too long (max {} characters).", MAX_CHAT_LEN))
+        }
+        let lobby = self.lobby.lock().unwrap();
+        let game = match self.game.and_then(|id| lobby.games.get(&id)) {
+            Some(game) => game,
+            None => return CommandResult::Failure(String::from("Not in a game."))
+        };
+        let side = match game.side_of(self.conn) {
+            Some(side) => side,
+            None => return CommandResult::Failure(String::from("Not in a game."))
+        };
+        let line = format!("CHAT {} {} {}", game.id, side+1, text);
+        lobby.send_all(&game.audience(Some(self.conn)), &line);
+        CommandResult::Message(String::from("Message sent."))
+    }
+
+    // Suggests where to fire next, worked out from what the player has seen
+    // of the opponent's fleet so far and never from where its ships really are.
+    fn hint(&mut self) -> CommandResult {
+        let (fog, limit) = {
+            let mut lobby = self.lobby.lock().unwrap();
+            let limit = lobby.hint_limit;
+            let game = match self.game.and_then(|id| lobby.games.get_mut(&id)) {
+                Some(game) => game,
+                None => return CommandResult::Failure(String::from("Not in a game."))
+            };
+            let side = match game.side_of(self.conn) {
+                Some(side) => side,
+                None => return CommandResult::Failure(String::from("Not in a game."))
+            };
+            if !game.is_started() {
+                return CommandResult::Failure(String::from("Waiting for opponent."))
+            }
+            if !game.is_underway() {
+                return CommandResult::Failure(String::from("Waiting for fleets to be placed."))
+            }
+            if game.players[side].hints >= limit {
+                return CommandResult::Failure(format!("No hints left ({} per game).", limit))
+            }
+            let fog = match game.players[1 - side].fleet.as_ref() {
+                Some(fleet) => FogGrid::from_fog_of_war(&fleet.get_fog_of_war()),
+                None => return CommandResult::Failure(String::from("Not in a game."))
+            };
+            (fog, limit)
+        };
+        // The lobby is not held while the solver runs.
+        let map = Solver::default().solve(&fog, &mut rand::thread_rng());
+        let best = match map.best(&fog) {
+            Some(best) => best,
+            None => return CommandResult::Failure(String::from("No cells left to try."))
+        };
+        // Only a hint actually given counts against the limit and the score.
+        let mut lobby = self.lobby.lock().unwrap();
+        let game = match self.game.and_then(|id| lobby.games.get_mut(&id)) {
+            Some(game) => game,
+            None => return CommandResult::Failure(String::from("Not in a game."))
+        };
+        let player = match game.side_of(self.conn) {
+            Some(side) => &mut game.players[side],
+            None => return CommandResult::Failure(String::from("Not in a game."))
+        };
+        player.hints += 1;
+        let used = player.hints;
+        CommandResult::Message(format!("HINT {} {} {}", best, limit.saturating_sub(used), render_heatmap(&fog, &map)))
+    }
+
+    // Registering also logs in. Games are recorded under the account from the next one on.
+    fn login(&mut self, name: String, password: &str, register: bool) -> CommandResult {
+        // Passwords are hashed with neither lock held, so slow hashing holds up nobody else.
+        let accounts = self.lobby.lock().unwrap().accounts.clone();
+        if register {
+            let rounds = {
+                let accounts = accounts.lock().unwrap();
+                if accounts.exists(&name) {
+                    return CommandResult::Failure(String::from("Name already taken."))
+                }
+                accounts.get_rounds()
+            };
+            if let Err(msg) = AccountStore::check_password(password) {
+                return CommandResult::Failure(msg)
+            }
+            let credentials = Credentials::new(password, rounds);
+            if let Err(msg) = accounts.lock().unwrap().insert(&name, credentials) {
+                return CommandResult::Failure(msg)
+            }
+        }
+        else {
+            let stored = accounts.lock().unwrap().get_credentials(&name);
+            if !stored.is_some_and(|c| c.check(password)) {
+                return CommandResult::Failure(String::from("Wrong name or password."))
+            }
+        }
+
+        let mut lobby = self.lobby.lock().unwrap();
+        if lobby.identities.iter().any(|(conn, n)| *n == name && *conn != self.conn) {
+            return CommandResult::Failure(String::from("Already logged in elsewhere."))
+        }
+        lobby.identities.insert(self.conn, name.clone());
+        let msg = format!("Logged in as {}.", name);
+        self.name = Some(name);
+        CommandResult::Message(msg)
+    }
+
+    // Lifetime statistics of a player, by default this one.
+    fn stats(&self, name: Option<String>) -> CommandResult {
+        let own = match &self.name {
+            Some(own) => own.clone(),
+            None => return CommandResult::Failure(String::from("Not logged in. Use LOGIN <name> <password> first."))
+        };
+        let name = name.unwrap_or(own);
+        CommandResult::Message(self.lobby.lock().unwrap().stats.report(&name))
+    }
+
+    // Leaves the current game, if any.
+    fn leave(&mut self) {
+        if let Some(id) = self.game.take() {
+            self.lobby.lock().unwrap().leave_game(id, self.conn);
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.leave();
+        self.lobby.lock().unwrap().disconnect(self.conn);
+    }
+}
+
+
+// Hides passwords and seat tokens from the server's log.
+pub fn redact(line: &str) -> String {
+    let words: Vec<&str> = line.splitn(3, ' ').collect();
+    match words.as_slice() {
+        [cmd @ "REGISTER", name, _] | [cmd @ "LOGIN", name, _] | [cmd @ "RESUME", name, _] => format!("{} {} ***", cmd, name),
+        _ => line.to_string()
+    }
+}
+
+
+// Control characters would let a player mess with the other side's
+// terminal or break the line protocol, so they are turned into spaces.
+pub fn sanitize_chat(text: &str) -> String {
+    let cleaned: String = text.chars()
+        .map(|c| if c.is_control() {' '} else {c})
+        .collect();
+    cleaned.trim().to_string()
+}
+
+
+fn write_loop<S: Stream>(stream: S, outbox: Receiver<String>) -> Result<(), Error> {
+    let mut writer = BufWriter::new(stream);
+    for msg in outbox {
+        writer.write_all(msg.as_bytes())?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+
+// Serves one client over a plain TCP or a TLS stream.
+pub fn connection_handler<S: Stream>(mut stream: S, lobby: SharedLobby) -> Result<(), Error> {
+    let peer = stream.peer_addr()?;
+    let _admission = match Admission::new(&lobby, peer.ip()) {
+        Ok(admission) => admission,
+        Err(reason) => {
+            log_event!(Warn, "refused", peer = peer, reason = reason);
+            writeln!(stream, "{}", reason).ok();
+            stream.shutdown();
+            return Ok(())
+        }
+    };
+    let max_line = lobby.lock().unwrap().limits.max_line_length;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let (outbox, inbox) = mpsc::channel();
+    let writer_stream = stream.try_clone()?;
+    let writer = thread::spawn(move || write_loop(writer_stream, inbox));
+    let closer = stream.try_clone()?;
+    let mut session = Session::new(lobby.clone(), outbox);
+    let handle = ClientHandle::new(peer, move || closer.stop_reading());
+    lobby.lock().unwrap().set_handle(session.get_conn(), handle);
+    METRICS.connection_opened();
+    log_event!(Info, "connect", conn = session.get_conn(), peer = peer, transport = S::TRANSPORT);
+
+    loop {
+        let mut response = String::new();
+        // One byte over the limit tells a line that is too long from one that just fits.
+        let bytes_read = match (&mut reader).take(max_line as u64 + 1).read_line(&mut response) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) => {
+                log_event!(Warn, "read_failed", conn = session.get_conn(), peer = peer, error = e);
+                break;
+            }
+        };
+        if bytes_read > max_line && !response.ends_with('\n') {
+            session.reply(&format!("Line too long (at most {} bytes).", max_line));
+            break;
+        }
+        let resp = response.trim();
+        if !session.handle_line(resp) {
+            break;
+        }
+    }
+
+    log_event!(Info, "disconnect", conn = session.get_conn(), peer = peer);
+    // Dropping the session's outbox lets the writer finish what is queued.
+    session.close();
+    drop(session);
+    writer.join().ok();
+    stream.shutdown();
+    Ok(())
+}
+
+
+// Ends idle HTTP games and unclaimed restored games as time goes by. Never returns.
+pub fn reap(lobby: SharedLobby) {
+    loop {
+        thread::sleep(REAP_INTERVAL);
+        lobby.lock().unwrap().expire_idle_games(Instant::now());
+    }
+}
+
+
+// Turns new connections away and warns players `grace` ahead, then saves every
+// game to the snapshot file (ending them instead if there is none) and disconnects everybody.
+// Returns once the connections are closed, or after a few seconds at most.
+pub fn shutdown(lobby: &SharedLobby, grace: Duration) {
+    lobby.lock().unwrap().closing = true;
+    if !grace.is_zero() {
+        lobby.lock().unwrap().broadcast(&format!("Server shutting down in {} seconds.", grace.as_secs()));
+        thread::sleep(grace);
+    }
+    {
+        let mut lobby = lobby.lock().unwrap();
+        if let Some(path) = lobby.snapshot.clone() {
+            match lobby.save_games(&path) {
+                Ok(count) => log_event!(Info, "games_saved", count = count, path = path.display()),
+                Err(e) => log_event!(Error, "save_failed", error = e)
+            }
+        }
+        let games: Vec<GameId> = lobby.games.keys().copied().collect();
+        for id in games {
+            lobby.end_game(id);
+        }
+        let conns: Vec<ConnId> = lobby.clients.keys().copied().collect();
+        for conn in conns {
+            lobby.kick(conn, "Server shutting down.");
+        }
+    }
+    for _ in 0..50 {
+        if lobby.lock().unwrap().clients.is_empty() {
+            break
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+
+pub fn serve(listener: TcpListener, lobby: SharedLobby) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let lobby = lobby.clone();
+                thread::spawn(move || -> Result<(), Error> {
+                    connection_handler(stream, lobby)?;
+                    Ok(())
+                });
+            }
+            Err(e) => log_event!(Warn, "accept_failed", error = e)
+        }
+    }
+}
+
+
+// Same as serve, every client having to speak TLS first.
+pub fn serve_tls(listener: TcpListener, lobby: SharedLobby, config: Arc<rustls::ServerConfig>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let lobby = lobby.clone();
+                let config = config.clone();
+                thread::spawn(move || -> Result<(), Error> {
+                    let peer = stream.peer_addr()?;
+                    let stream = match TlsStream::accept(stream, config) {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            log_event!(Warn, "handshake_failed", peer = peer, error = e);
+                            return Ok(())
+                        }
+                    };
+                    if let Err(e) = connection_handler(stream, lobby) {
+                        log_event!(Warn, "connection_failed", peer = peer, error = e);
+                    }
+                    Ok(())
+                });
+            }
+            Err(e) => log_event!(Warn, "accept_failed", error = e)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BoardConfig;
+
+    fn session(lobby: &SharedLobby) -> (Session, Receiver<String>) {
+        let (outbox, inbox) = mpsc::channel();
+        (Session::new(lobby.clone(), outbox), inbox)
+    }
+
+    // A lobby whose passwords are quick to hash.
+    fn lobby_with_accounts() -> SharedLobby {
+        let lobby = Lobby::shared();
+        let mut accounts = AccountStore::in_memory();
+        accounts.set_rounds(10);
+        lobby.lock().unwrap().set_accounts(accounts);
+        lobby
+    }
+
+    #[test]
+    fn spectators_follow_shots() {
+        let lobby = Lobby::shared();
+        let (mut host, host_inbox) = session(&lobby);
+        let (mut guest, _guest_inbox) = session(&lobby);
+        let (mut fan, fan_inbox) = session(&lobby);
+
+        host.handle_line("NEWMATCH");
+        assert_eq!(host_inbox.try_recv().unwrap(), "Match 1 created. Waiting for opponent.");
+        guest.handle_line("JOIN 1");
+        assert_eq!(host_inbox.try_recv().unwrap(), "JOINED 1 2");
+        host.handle_line("CELL:[2,1]");
+        assert_eq!(host_inbox.try_recv().unwrap(), "Waiting for fleets to be placed.");
+        for player in [&mut host, &mut guest].iter_mut() {
+            player.handle_line("AUTO");
+            player.handle_line("READY");
+        }
+        while host_inbox.try_recv().is_ok() {}
+
+        fan.handle_line("WATCH 1");
+        assert_eq!(fan_inbox.try_recv().unwrap(), "Watching game 1.");
+        assert!(fan_inbox.try_recv().unwrap().starts_with("FOG 1 1 ........./"));
+        assert!(fan_inbox.try_recv().unwrap().starts_with("FOG 1 2 "));
+
+        fan.handle_line("CELL:[1,1]");
+        assert_eq!(fan_inbox.try_recv().unwrap(), "Spectators cannot fire.");
+
+        host.handle_line("CELL:[2,1]");
+        host_inbox.try_recv().unwrap();
+        let shot = fan_inbox.try_recv().unwrap();
+        assert!(shot == "SHOT 1 1 B1 HIT" || shot == "SHOT 1 1 B1 MISS");
+        let fog = fan_inbox.try_recv().unwrap();
+        assert!(fog.starts_with("FOG 1 2 ."));
+        assert!(fog.chars().nth(9) == Some('X') || fog.chars().nth(9) == Some('o'));
+
+        // The host walking out hands the win to the guest; spectators see both fleets.
+        host.close();
+        assert!(fan_inbox.try_recv().unwrap().starts_with("REVEAL 1 1 "));
+        assert!(fan_inbox.try_recv().unwrap().starts_with("REVEAL 1 2 "));
+        assert_eq!(fan_inbox.try_recv().unwrap(), "GAMEOVER 1 2");
+    }
+
+    #[test]
+    fn fleet_placement() {
+        let lobby = Lobby::shared();
+        let (mut host, host_inbox) = session(&lobby);
+        let (mut guest, guest_inbox) = session(&lobby);
+
+        host.handle_line("PLACE C5 A1 Right");
+        assert_eq!(host_inbox.try_recv().unwrap(), "Not in a match.");
+        host.handle_line("NEWMATCH");
+        guest.handle_line("JOIN 1");
+        while host_inbox.try_recv().is_ok() {}
+        assert_eq!(guest_inbox.try_recv().unwrap(), "Joined match 1. You are player 2.");
+        assert_eq!(guest_inbox.try_recv().unwrap(), "JOINED 1 2");
+
+        host.handle_line("PLACE C5 A1 Right");
+        assert_eq!(host_inbox.try_recv().unwrap(), "C5 placed.");
+        host.handle_line("PLACE H4 G1 Right");
+        assert_eq!(host_inbox.try_recv().unwrap(), "Ship fell outside the board.");
+        host.handle_line("PLACE H4 C1 Down");
+        assert_eq!(host_inbox.try_recv().unwrap(), "Collision with another ship.");
+        host.handle_line("READY");
+        assert_eq!(host_inbox.try_recv().unwrap(), "Fleet incomplete, still to place: H4 L3 A2.");
+        host.handle_line("AUTO");
+        assert_eq!(host_inbox.try_recv().unwrap(), "Fleet placed.");
+        host.handle_line("READY");
+        assert_eq!(host_inbox.try_recv().unwrap(), "READY");
+        assert_eq!(guest_inbox.try_recv().unwrap(), "READY 1 1");
+        host.handle_line("PLACE A2 I8 Down");
+        assert_eq!(host_inbox.try_recv().unwrap(), "Fleet already in place.");
+
+        // Ships stay where the player put them.
+        let lobby = lobby.lock().unwrap();
+        let fleet = lobby.get_game(1).unwrap().players[0].fleet.as_ref().unwrap();
+        assert_eq!(fleet.get_contents()[4][0], '1');
+    }
+
+    #[test]
+    fn chat_reaches_opponent_and_spectators() {
+        let lobby = Lobby::shared();
+        let (mut host, host_inbox) = session(&lobby);
+        let (mut guest, guest_inbox) = session(&lobby);
+        let (mut fan, fan_inbox) = session(&lobby);
+
+        host.handle_line("SAY anyone?");
+        assert_eq!(host_inbox.try_recv().unwrap(), "Not in a game.");
+
+        host.handle_line("NEWMATCH");
+        guest.handle_line("JOIN 1");
+        fan.handle_line("WATCH 1");
+        while fan_inbox.try_recv().is_ok() {}
+        while host_inbox.try_recv().is_ok() {}
+        while guest_inbox.try_recv().is_ok() {}
+
+        guest.handle_line("SAY good\x1b[2J luck\u{7}");
+        assert_eq!(guest_inbox.try_recv().unwrap(), "Message sent.");
+        assert_eq!(host_inbox.try_recv().unwrap(), "CHAT 1 2 good [2J luck");
+        assert_eq!(fan_inbox.try_recv().unwrap(), "CHAT 1 2 good [2J luck");
+
+        host.handle_line(&format!("SAY {}", "x".repeat(MAX_CHAT_LEN + 1)));
+        assert!(host_inbox.try_recv().unwrap().starts_with("Message too long"));
+        assert!(guest_inbox.try_recv().is_err());
+
+        fan.handle_line("SAY hello");
+        assert_eq!(fan_inbox.try_recv().unwrap(), "Not in a game.");
+    }
+
+    #[test]
+    fn hints_are_capped_and_cost_points() {
+        let lobby = Lobby::shared();
+        lobby.lock().unwrap().set_hint_limit(2);
+        let (mut player, inbox) = session(&lobby);
+
+        player.handle_line("HINT");
+        assert_eq!(inbox.try_recv().unwrap(), "Not in a game.");
+        player.handle_line("STARTGAME");
+        inbox.try_recv().unwrap();
+
+        player.handle_line("HINT");
+        let hint = inbox.try_recv().unwrap();
+        let parts: Vec<&str> = hint.split(' ').collect();
+        assert_eq!(parts[0], "HINT");
+        assert!(Position::from_coord(parts[1]).is_some());
+        assert_eq!(parts[2], "1");
+        assert_eq!(parts[3].len(), 9*9 + 8);
+        assert!(parts[3].chars().all(|c| c == '/' || c.is_ascii_digit()));
+        player.handle_line("HINT");
+        assert!(inbox.try_recv().unwrap().starts_with("HINT "));
+        player.handle_line("HINT");
+        assert_eq!(inbox.try_recv().unwrap(), "No hints left (2 per game).");
+
+        // Sinking the fleet without a single miss scores 81 - 14, less the hints.
+        let ships: Vec<Position> = lobby.lock().unwrap().get_game(1).unwrap().players[1]
+            .fleet.as_ref().unwrap().get_occupied_cells().into_iter().collect();
+        for pos in ships {
+            player.handle_line(&format!("CELL:[{},{}]", pos.x, pos.y));
+        }
+        let replies: Vec<String> = inbox.try_iter().collect();
+        assert_eq!(replies.last().unwrap(), &format!("Game successcully completed. Score {}", 81 - 14 - 2 * HINT_PENALTY));
+    }
+
+    #[test]
+    fn stats_follow_named_players() {
+        let lobby = lobby_with_accounts();
+        let (mut host, host_inbox) = session(&lobby);
+        let (mut guest, _guest_inbox) = session(&lobby);
+
+        host.handle_line("STATS");
+        assert_eq!(host_inbox.try_recv().unwrap(), "Not logged in. Use LOGIN <name> <password> first.");
+        host.handle_line("REGISTER ann secret1");
+        assert_eq!(host_inbox.try_recv().unwrap(), "Logged in as ann.");
+        host.handle_line("STATS");
+        assert_eq!(host_inbox.try_recv().unwrap(), "No games recorded for ann.");
+
+        // The guest walks out before anyone has fired, which records nothing.
+        host.handle_line("NEWMATCH");
+        guest.handle_line("JOIN 1");
+        guest.close();
+        while host_inbox.try_recv().is_ok() {}
+        host.handle_line("STATS");
+        assert_eq!(host_inbox.try_recv().unwrap(), "No games recorded for ann.");
+
+        // A solo game sunk without a miss.
+        host.handle_line("STARTGAME");
+        let ships: Vec<Position> = lobby.lock().unwrap().get_game(2).unwrap().players[1]
+            .fleet.as_ref().unwrap().get_occupied_cells().into_iter().collect();
+        for pos in ships {
+            host.handle_line(&format!("CELL:[{},{}]", pos.x, pos.y));
+        }
+        let lobby = lobby.lock().unwrap();
+        let game = lobby.get_stats().get_games("ann")[0];
+        assert_eq!((game.won, game.shots, game.hits, game.shots_to_first_hit), (true, 14, 14, Some(1)));
+        assert_eq!(game.opponent, "computer");
+        assert_eq!(game.hunt_shots + game.target_shots, 14);
+        assert!(lobby.get_stats().get_games("guest").is_empty());
+    }
+
+    #[test]
+    fn players_log_in_to_their_accounts() {
+        let lobby = lobby_with_accounts();
+        let (mut ann, ann_inbox) = session(&lobby);
+        let (mut other, other_inbox) = session(&lobby);
+
+        ann.handle_line("LOGIN ann secret1");
+        assert_eq!(ann_inbox.try_recv().unwrap(), "Wrong name or password.");
+        ann.handle_line("REGISTER ann 12345");
+        assert_eq!(ann_inbox.try_recv().unwrap(), "Password too short (at least 6 characters).");
+        ann.handle_line("REGISTER ann secret1");
+        assert_eq!(ann_inbox.try_recv().unwrap(), "Logged in as ann.");
+
+        other.handle_line("REGISTER ann secret2");
+        assert_eq!(other_inbox.try_recv().unwrap(), "Name already taken.");
+        other.handle_line("LOGIN ann secret2");
+        assert_eq!(other_inbox.try_recv().unwrap(), "Wrong name or password.");
+        other.handle_line("LOGIN ann secret1");
+        assert_eq!(other_inbox.try_recv().unwrap(), "Already logged in elsewhere.");
+        ann.close();
+        other.handle_line("LOGIN ann secret1");
+        assert_eq!(other_inbox.try_recv().unwrap(), "Logged in as ann.");
+
+        assert_eq!(redact("LOGIN ann secret1"), "LOGIN ann ***");
+        assert_eq!(redact("SAY LOGIN ann secret1"), "SAY LOGIN ann secret1");
+    }
+
+    #[test]
+    fn matches_between_named_players_are_ranked() {
+        let lobby = lobby_with_accounts();
+        let (mut ann, ann_inbox) = session(&lobby);
+        let (mut bob, _bob_inbox) = session(&lobby);
+
+        ann.handle_line("REGISTER ann secret1");
+        bob.handle_line("REGISTER bob secret2");
+
+        // Walking out before anyone has fired ends the match unranked.
+        ann.handle_line("NEWMATCH");
+        bob.handle_line("JOIN 1");
+        bob.close();
+        let (mut bob, _bob_inbox) = session(&lobby);
+        bob.handle_line("LOGIN bob secret2");
+        assert!(lobby.lock().unwrap().get_leaderboard().get("ann").is_none());
+
+        // A match played to the end is. It takes more commands than the default burst.
+        let limits = Limits{commands_per_second: 1000.0, command_burst: 1000.0, ..Limits::default()};
+        lobby.lock().unwrap().apply_config(&Config{limits, ..Config::default()});
+        ann.handle_line("NEWMATCH");
+        bob.handle_line("JOIN 2");
+        for player in [&mut ann, &mut bob] {
+            player.handle_line("AUTO");
+            player.handle_line("READY");
+        }
+        let targets: Vec<Position> = lobby.lock().unwrap().get_game(2).unwrap().players[1]
+            .fleet.as_ref().unwrap().get_occupied_cells().into_iter().collect();
+        for (i, pos) in targets.iter().enumerate() {
+            ann.handle_line(&format!("CELL:[{},{}]", pos.x, pos.y));
+            bob.handle_line(&format!("CELL:[{},{}]", i % 9 + 1, i / 9 + 1));
+        }
+        while ann_inbox.try_recv().is_ok() {}
+
+        ann.handle_line("LEADERBOARD");
+        assert_eq!(ann_inbox.try_recv().unwrap(), "LEADERBOARD 2\n1 ann 1508 1 1 0\n2 bob 1492 1 0 1");
+        ann.handle_line("LEADERBOARD 1");
+        assert_eq!(ann_inbox.try_recv().unwrap(), "LEADERBOARD 1\n1 ann 1508 1 1 0");
+
+        // Solo games do not count.
+        ann.handle_line("STARTGAME");
+        ann.handle_line("STARTGAME");
+        assert_eq!(lobby.lock().unwrap().get_leaderboard().get("ann").unwrap().games, 1);
+    }
+
+    #[test]
+    fn http_games_are_capped_and_expire() {
+        let mut lobby = Lobby::new();
+        let fleet = || {
+            let mut board = Board::new();
+            board.setup(BoardConfig::Auto).unwrap();
+            board
+        };
+        for _ in 0..MAX_API_GAMES {
+            lobby.add_api_game(fleet()).unwrap();
+        }
+        assert_eq!(lobby.add_api_game(fleet()).unwrap_err(), "Too many games in progress, try again later.");
+
+        // Game 1 is fired at after a while, the others are left alone; matches never expire.
+        lobby.fire(1, 0, 1, 1, None);
+        lobby.games.get_mut(&1).unwrap().last_shot += API_GAME_IDLE;
+        lobby.add_game(|id| Game::new_match(id, 1, None));
+        lobby.expire_idle_games(Instant::now() + API_GAME_IDLE + Duration::from_secs(1));
+        let left: Vec<GameId> = lobby.get_games().iter().map(|g| g.get_id()).collect();
+        assert_eq!(left.len(), 2);
+        assert!(left.contains(&1) && left.contains(&(MAX_API_GAMES as GameId + 1)));
+        assert!(lobby.add_api_game(fleet()).is_ok());
+    }
+
+    #[test]
+    fn abusive_clients_are_held_back() {
+        let lobby = Lobby::shared();
+        let limits = Limits{max_connections_per_ip: 1, commands_per_second: 0.01, command_burst: 3.0, max_line_length: 16,
+                            invalid_commands_per_minute: 1.0, ban_seconds: 60};
+        lobby.lock().unwrap().apply_config(&Config{limits, ..Config::default()});
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_lobby = lobby.clone();
+        thread::spawn(move || serve(listener, server_lobby));
+        let connect = || {
+            let stream = std::net::TcpStream::connect(addr).unwrap();
+            (BufReader::new(stream.try_clone().unwrap()), stream)
+        };
+        let line = |reader: &mut BufReader<std::net::TcpStream>| {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            line.trim_end().to_string()
+        };
+
+        let (mut reader, mut client) = connect();
+        client.write_all(b"NEWMATCH\n").unwrap();
+        assert_eq!(line(&mut reader), "Match 1 created. Waiting for opponent.");
+        let (mut second, _) = connect();
+        assert_eq!(line(&mut second), "Too many connections from 127.0.0.1 (at most 1).");
+
+        client.write_all(b"SAY hi\nSAY hi\nSAY hi\n").unwrap();
+        line(&mut reader);
+        line(&mut reader);
+        assert_eq!(line(&mut reader), "Too many commands, slow down.");
+        client.write_all(b"SAY this line is far too long\n").unwrap();
+        assert_eq!(line(&mut reader), "Line too long (at most 16 bytes).");
+        assert_eq!(line(&mut reader), "");
+
+        // A fresh bucket, on a session of its own, for the invalid commands.
+        let (mut spammer, inbox) = session(&lobby);
+        lobby.lock().unwrap().set_handle(spammer.get_conn(), ClientHandle::new("10.0.0.9:4000".parse().unwrap(), || ()));
+        assert!(spammer.handle_line("FOO"));
+        inbox.try_recv().unwrap();
+        assert!(!spammer.handle_line("FOO"));
+        assert_eq!(inbox.try_recv().unwrap(), "Banned for 60 seconds: too many invalid commands.");
+        assert_eq!(Admission::new(&lobby, "10.0.0.9".parse().unwrap()).err().unwrap(), "Banned for another 60 seconds.");
+    }
+
+    #[test]
+    fn saved_games_are_resumed_after_a_restart() {
+        let path = std::env::temp_dir().join(format!("battleship-games-{}.json", std::process::id()));
+        let lobby = Lobby::shared();
+        let (mut ann, ann_inbox) = session(&lobby);
+        let (mut bob, bob_inbox) = session(&lobby);
+        ann.handle_line("NEWMATCH");
+        bob.handle_line("JOIN 1");
+        for player in [&mut ann, &mut bob] {
+            player.handle_line("AUTO");
+            player.handle_line("READY");
+        }
+        ann.handle_line("CELL:[1,1]");
+        while ann_inbox.try_recv().is_ok() {}
+        while bob_inbox.try_recv().is_ok() {}
+
+        assert_eq!(lobby.lock().unwrap().save_games(&path), Ok(1));
+        let seat = |line: String| line.strip_prefix("SAVED 1 ").unwrap().to_string();
+        let (ann_seat, bob_seat) = (seat(ann_inbox.try_recv().unwrap()), seat(bob_inbox.try_recv().unwrap()));
+        assert!(lobby.lock().unwrap().get_games().is_empty());
+
+        let restarted = Lobby::shared();
+        let saved = snapshot::load(&path).unwrap().unwrap();
+        assert!(path.exists());
+        assert_eq!(restarted.lock().unwrap().restore_games(saved), 1);
+        assert_eq!(restarted.lock().unwrap().games_report(), "GAMES 1\n1 match underway away,away spectators=0 shots=1");
+
+        let (mut ann, ann_inbox) = session(&restarted);
+        let (mut bob, bob_inbox) = session(&restarted);
+        ann.handle_line(&format!("RESUME 1 {}", bob_seat.replace(|c: char| c.is_ascii_digit(), "a")));
+        assert_eq!(ann_inbox.try_recv().unwrap(), "Wrong token for this game.");
+        ann.handle_line(&format!("RESUME 1 {}", ann_seat));
+        assert_eq!(ann_inbox.try_recv().unwrap(), "Resumed game 1. You are player 1. Player 2 to fire.");
+        assert!(ann_inbox.try_recv().unwrap().starts_with("FOG 1 1 "));
+        assert!(ann_inbox.try_recv().unwrap().starts_with("FOG 1 2 "));
+        bob.handle_line(&format!("RESUME 1 {}", bob_seat));
+        assert_eq!(bob_inbox.try_recv().unwrap(), "Resumed game 1. You are player 2. Player 2 to fire.");
+        assert_eq!(ann_inbox.try_recv().unwrap(), "JOINED 1 2");
+        // The seat is taken now.
+        let (mut eve, eve_inbox) = session(&restarted);
+        eve.handle_line(&format!("RESUME 1 {}", bob_seat));
+        assert_eq!(eve_inbox.try_recv().unwrap(), "Wrong token for this game.");
+
+        bob.handle_line("CELL:[1,1]");
+        assert!(ann_inbox.try_recv().unwrap().starts_with("SHOT 1 2 A1 "));
+        assert_eq!(restarted.lock().unwrap().get_game(1).unwrap().get_replay(None).shots.len(), 2);
+
+        // Had the server crashed, the file would still be there. Walking out on
+        // a player who never came back ends the game without a winner...
+        let crashed = Lobby::shared();
+        crashed.lock().unwrap().restore_games(snapshot::load(&path).unwrap().unwrap());
+        let (mut ann, ann_inbox) = session(&crashed);
+        ann.handle_line(&format!("RESUME 1 {}", ann_seat));
+        ann.close();
+        assert_eq!(ann_inbox.try_iter().last().unwrap(), "ENDED 1");
+        assert_eq!(crashed.lock().unwrap().get_replay(1).unwrap().winner, None);
+
+        // ...and one nobody comes back to is ended after a while.
+        let mut lobby = Lobby::new();
+        lobby.restore_games(snapshot::load(&path).unwrap().unwrap());
+        lobby.expire_idle_games(Instant::now() + RESUME_WINDOW / 2);
+        assert_eq!(lobby.get_games().len(), 1);
+        lobby.expire_idle_games(Instant::now() + RESUME_WINDOW + Duration::from_secs(1));
+        assert!(lobby.get_games().is_empty());
+
+        // Saving with no games left clears the file.
+        assert_eq!(lobby.save_games(&path), Ok(0));
+        assert!(!path.exists());
+    }
+}
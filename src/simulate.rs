@@ -0,0 +1,305 @@
+// Headless games of a shooting strategy against computer placed fleets,
+// for measuring strategies over many games.
+//
+// Game i is played with a generator seeded from (seed, i), so a run gives
+// the same results whatever the number of threads.
+
+use std::thread;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::Serialize;
+
+use crate::{Board, BoardConfig, Position};
+use crate::placement::PlacementPolicy;
+use crate::strategy::{FogGrid, Strategy};
+
+
+pub struct SimConfig {
+    pub games: usize,
+    pub seed: u64,
+    pub threads: usize,
+    pub placement: PlacementPolicy,  // How the fleets being shot at are set up
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig{games: 1000, seed: 0, threads: 4, placement: PlacementPolicy::Random}
+    }
+}
+
+
+// Seed for the i-th game of a run (splitmix64), so neighbouring games get unrelated boards.
+pub fn game_seed(seed: u64, game: u64) -> u64 {
+    let mut z = seed.wrapping_add(game.wrapping_add(1).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+
+pub struct GameRecord {
+    pub shots: u32,
+    pub completed: bool,  // False for forfeits and games cut off
+    pub failure: Option<String>,  // Why the strategy forfeited
+    pub fired: Vec<(Position, bool)>,
+}
+
+
+// Plays one game to the end. A strategy that keeps missing the board or
+// repeating itself is cut off after 81 * 4 shots, one that forfeits right away.
+pub fn play_game(strategy: &mut dyn Strategy, placement: PlacementPolicy, rng: &mut StdRng) -> GameRecord {
+    let mut board = Board::new();
+    board.setup_with_rng(BoardConfig::Policy(placement), rng).unwrap();
+    let mut fog = FogGrid::new();
+    let mut fired = Vec::new();
+    while !board.is_game_complete() && fog.get_shots() < 81 * 4 {
+        let pos = strategy.next_shot(&fog, rng);
+        if strategy.has_forfeited() {
+            break;
+        }
+        let outcome = board.fire(pos);
+        fog.record(pos, outcome);
+        strategy.observe(pos, outcome);
+        fired.push((pos, outcome != crate::ShotOutcome::Miss));
+    }
+    GameRecord{shots: fog.get_shots(), completed: board.is_game_complete(), failure: strategy.get_failure().map(String::from), fired}
+}
+
+
+#[derive(Serialize, Debug)]
+pub struct Percentile {
+    pub percentile: u32,
+    pub shots: u32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HistogramBucket {
+    pub shots: u32,
+    pub games: u32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CellStats {
+    pub cell: String,
+    pub shots: u64,
+    pub hits: u64,
+    pub hit_rate: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SimReport {
+    pub strategy: String,
+    pub placement: String,
+    pub games: usize,
+    pub unfinished: usize,  // Forfeited or cut off, and left out of the shot counts below
+    pub seed: u64,
+    pub mean: f64,
+    pub median: f64,
+    pub min: u32,
+    pub max: u32,
+    pub percentiles: Vec<Percentile>,
+    pub histogram: Vec<HistogramBucket>,
+    pub cells: Vec<CellStats>,
+    #[serde(skip)]
+    pub failures: Vec<String>,  // Why each forfeited game was forfeited, in game order
+}
+
+impl SimReport {
+    pub const PERCENTILES: [u32; 6] = [10, 25, 75, 90, 95, 99];
+
+    fn new(strategy: String, config: &SimConfig, mut shots: Vec<u32>, unfinished: usize, cell_shots: &[u64], cell_hits: &[u64]) -> SimReport {
+        shots.sort_unstable();
+        let n = shots.len();
+        let mean = if n > 0 {shots.iter().map(|s| *s as f64).sum::<f64>() / n as f64} else {0.0};
+        let median = match n {
+            0 => 0.0,
+            _ if n % 2 == 1 => shots[n/2] as f64,
+            _ => (shots[n/2 - 1] + shots[n/2]) as f64 / 2.0
+        };
+        let percentiles = Self::PERCENTILES.iter()
+            .map(|p| Percentile{percentile: *p, shots: nearest_rank(&shots, *p)})
+            .collect();
+
+        let mut histogram: Vec<HistogramBucket> = Vec::new();
+        for s in shots.iter() {
+            match histogram.last_mut() {
+                Some(bucket) if bucket.shots == *s => bucket.games += 1,
+                _ => histogram.push(HistogramBucket{shots: *s, games: 1})
+            }
+        }
+
+        let cells = FogGrid::all_positions().enumerate()
+            .map(|(i, pos)| CellStats{
+                cell: pos.to_string(),
+                shots: cell_shots[i],
+                hits: cell_hits[i],
+                hit_rate: if cell_shots[i] > 0 {cell_hits[i] as f64 / cell_shots[i] as f64} else {0.0},
+            })
+            .collect();
+
+        SimReport{
+            strategy, placement: config.placement.to_string(), games: n + unfinished, unfinished, seed: config.seed, mean, median,
+            min: shots.first().copied().unwrap_or(0),
+            max: shots.last().copied().unwrap_or(0),
+            percentiles, histogram, cells, failures: Vec::new()
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    // Three tables separated by blank lines: summary, histogram and per-cell hit rates.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("metric,value\n");
+        out += &format!("strategy,{}\nplacement,{}\ngames,{}\nunfinished,{}\nseed,{}\nmean,{:.3}\nmedian,{}\nmin,{}\nmax,{}\n",
+                        self.strategy, self.placement, self.games, self.unfinished, self.seed, self.mean, self.median, self.min, self.max);
+        for p in self.percentiles.iter() {
+            out += &format!("p{},{}\n", p.percentile, p.shots);
+        }
+        out += "\nshots,games\n";
+        for bucket in self.histogram.iter() {
+            out += &format!("{},{}\n", bucket.shots, bucket.games);
+        }
+        out += "\ncell,shots,hits,hit_rate\n";
+        for cell in self.cells.iter() {
+            out += &format!("{},{},{},{:.4}\n", cell.cell, cell.shots, cell.hits, cell.hit_rate);
+        }
+        out
+    }
+}
+
+
+fn nearest_rank(sorted: &[u32], percentile: u32) -> u32 {
+    if sorted.is_empty() {
+        return 0
+    }
+    let rank = (percentile as f64 / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+
+fn cell_index(pos: &Position) -> Option<usize> {
+    if FogGrid::contains(pos) {
+        Some(((pos.y - 1) * 9 + pos.x - 1) as usize)
+    }
+    else {
+        None
+    }
+}
+
+
+// Plays config.games games, each with a fresh strategy from `make`.
+pub fn simulate<F>(make: F, config: &SimConfig) -> SimReport
+    where F: Fn() -> Box<dyn Strategy> + Sync
+{
+    let threads = config.threads.max(1);
+    let results = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads).map(|t| {
+            let make = &make;
+            scope.spawn(move || {
+                let mut shots = Vec::new();
+                let mut unfinished = 0;
+                let mut failures = Vec::new();
+                let mut cell_shots = vec![0u64; 81];
+                let mut cell_hits = vec![0u64; 81];
+                for game in (t..config.games).step_by(threads) {
+                    let mut rng = StdRng::seed_from_u64(game_seed(config.seed, game as u64));
+                    let record = play_game(make().as_mut(), config.placement, &mut rng);
+                    if record.completed {
+                        shots.push(record.shots);
+                    }
+                    else {
+                        unfinished += 1;
+                    }
+                    if let Some(reason) = record.failure {
+                        failures.push((game, reason));
+                    }
+                    for (pos, hit) in record.fired {
+                        if let Some(i) = cell_index(&pos) {
+                            cell_shots[i] += 1;
+                            if hit {
+                                cell_hits[i] += 1;
+                            }
+                        }
+                    }
+                }
+                (shots, unfinished, failures, cell_shots, cell_hits)
+            })
+        }).collect();
+        workers.into_iter().map(|w| w.join().unwrap()).collect::<Vec<_>>()
+    });
+
+    let mut shots = Vec::with_capacity(config.games);
+    let mut unfinished = 0;
+    let mut failures = Vec::new();
+    let mut cell_shots = vec![0u64; 81];
+    let mut cell_hits = vec![0u64; 81];
+    for (s, u, f, cs, ch) in results {
+        shots.extend(s);
+        unfinished += u;
+        failures.extend(f);
+        for i in 0..81 {
+            cell_shots[i] += cs[i];
+            cell_hits[i] += ch[i];
+        }
+    }
+    failures.sort();
+    let mut report = SimReport::new(make().name(), config, shots, unfinished, &cell_shots, &cell_hits);
+    report.failures = failures.into_iter().map(|(game, reason)| format!("game {}: {}", game, reason)).collect();
+    report
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategy::strategy_by_name;
+
+    #[test]
+    fn runs_are_reproducible() {
+        let make = || strategy_by_name("hunt-target").unwrap();
+        let one = simulate(make, &SimConfig{games: 40, seed: 7, threads: 1, ..Default::default()});
+        let four = simulate(make, &SimConfig{games: 40, seed: 7, threads: 4, ..Default::default()});
+        assert_eq!((one.games, one.unfinished), (40, 0));
+        assert_eq!(one.to_json(), four.to_json());
+        assert!(one.min >= 14 && one.max <= 81);
+        let hits: u64 = one.cells.iter().map(|c| c.hits).sum();
+        assert_eq!(hits, 40 * 14);
+    }
+
+    // Gives up before its first shot.
+    struct Quitter;
+
+    impl Strategy for Quitter {
+        fn name(&self) -> String {
+            String::from("quitter")
+        }
+
+        fn next_shot(&mut self, fog: &FogGrid, rng: &mut StdRng) -> Position {
+            Position::new(1,1)
+        }
+
+        fn has_forfeited(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn forfeits_are_not_counted_as_wins() {
+        let report = simulate(|| Box::new(Quitter), &SimConfig{games: 10, threads: 2, ..Default::default()});
+        assert_eq!((report.games, report.unfinished), (10, 10));
+        assert!(report.histogram.is_empty());
+        assert_eq!((report.min, report.max, report.mean), (0, 0, 0.0));
+        assert!(report.to_csv().contains("games,10\nunfinished,10\n"));
+    }
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let shots = [20, 30, 40, 50];
+        assert_eq!(nearest_rank(&shots, 25), 20);
+        assert_eq!(nearest_rank(&shots, 50), 30);
+        assert_eq!(nearest_rank(&shots, 99), 50);
+    }
+}
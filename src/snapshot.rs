@@ -0,0 +1,70 @@
+// Games in progress, saved to a JSON file when the server stops and
+// restored when it starts again.
+//
+// Fleets are kept in the text form of layout.rs and the shots as in a
+// replay, so a game is rebuilt by placing the ships and firing the shots
+// again. Every human player is given a seat token to take their place back
+// with RESUME.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::server::{GameId, ShotRecord};
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedPlayer {
+    pub name: Option<String>,
+    pub seat: Option<String>,   // Token to RESUME with; None for the computer and HTTP players
+    pub fleet: Option<String>,  // Ships placed so far, one per line
+    pub ready: bool,
+    pub hints: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedGame {
+    pub id: GameId,
+    pub solo: bool,
+    pub turn: usize,            // Counted from 0
+    pub token: Option<String>,  // For games played over HTTP
+    pub players: Vec<SavedPlayer>,
+    pub shots: Vec<ShotRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Snapshot {
+    pub next_game: GameId,  // So restored ids are not handed out again
+    pub games: Vec<SavedGame>,
+}
+
+
+// Written to a temporary file first, as the leaderboard is.
+pub fn save(path: &Path, snapshot: &Snapshot) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(snapshot).unwrap();
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, json)
+        .and_then(|_| fs::rename(&temp, path))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// Reads the snapshot at `path`, if there is one. The file stays until the
+// next save replaces or removes it, so a crash in between loses nothing.
+pub fn load(path: &Path) -> Result<Option<Snapshot>, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("{}: {}", path.display(), e))
+    };
+    let snapshot = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(Some(snapshot))
+}
+
+pub fn remove(path: &Path) -> Result<(), String> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(format!("{}: {}", path.display(), e)),
+        _ => Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+// Where are the remaining ships likely to be? The solver looks at fleet
+// layouts consistent with everything observed so far (no ship on a miss,
+// every hit covered, each sunk ship lying on hits only and through the shot
+// that sank it, ships still afloat not yet completely hit, if sinkings are
+// reported at all) and counts how often each cell is occupied.
+//
+// When few enough layouts remain they are enumerated exactly. Otherwise
+// layouts are sampled: ships are placed one at a time, each uniformly among
+// the placements still open to it, and every sample is weighted by the
+// number of choices it had so the estimate is not skewed towards crowded
+// boards.
+
+use std::sync::OnceLock;
+
+use rand::Rng;
+use strum::IntoEnumIterator;
+
+use crate::{Mask, Position, ShipType};
+use crate::strategy::{CellState, FogGrid};
+
+
+pub(crate) fn bit(pos: &Position) -> Mask {
+    1 << ((pos.y - 1) * 9 + pos.x - 1)
+}
+
+
+// Every way to put a ship of `size` on the board. Worked out once per size.
+pub(crate) fn placements(size: i32) -> &'static [Mask] {
+    static PLACEMENTS: OnceLock<Vec<Vec<Mask>>> = OnceLock::new();
+    let all = PLACEMENTS.get_or_init(|| (0..=9).map(|size| {
+        let mut masks = Vec::new();
+        for y in 1..=9 {
+            for x in 1..=9 {
+                if x + size - 1 <= 9 {
+                    masks.push((0..size).fold(0, |m, i| m | bit(&Position::new(x + i, y))));
+                }
+                if y + size - 1 <= 9 && size > 1 {
+                    masks.push((0..size).fold(0, |m, i| m | bit(&Position::new(x, y + i))));
+                }
+            }
+        }
+        masks
+    }).collect());
+    all.get(size as usize).map_or(&[], |masks| masks.as_slice())
+}
+
+
+pub struct ProbabilityMap {
+    cells: [[f64; 9]; 9],  // Indexed [x-1][y-1], like Board::get_contents
+    layouts: u64,          // Layouts counted, or samples accepted
+    exact: bool,
+}
+
+impl ProbabilityMap {
+    pub fn get(&self, pos: &Position) -> f64 {
+        if FogGrid::contains(pos) {self.cells[pos.x as usize -1][pos.y as usize -1]} else {0.0}
+    }
+
+    pub fn get_layouts(&self) -> u64 {
+        self.layouts
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.exact
+    }
+
+    // Untried cells from most to least likely to hold a ship.
+    pub fn ranked(&self, fog: &FogGrid) -> Vec<(Position, f64)> {
+        let mut cells: Vec<(Position, f64)> = fog.untried().into_iter().map(|pos| (pos, self.get(&pos))).collect();
+        cells.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        cells
+    }
+
+    pub fn best(&self, fog: &FogGrid) -> Option<Position> {
+        self.ranked(fog).first().map(|(pos, _)| *pos)
+    }
+}
+
+
+pub struct Solver {
+    pub samples: usize,     // Layouts to sample when enumerating is too costly
+    pub exact_limit: u64,   // Enumerate when the search space is at most this big
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Solver{samples: 2000, exact_limit: 200_000}
+    }
+}
+
+struct Constraints {
+    hits: Mask,
+    ships: Vec<(ShipType, Vec<Mask>)>,  // Each ship with the placements open to it
+}
+
+impl Solver {
+    fn constraints(fog: &FogGrid) -> Constraints {
+        let mut hits: Mask = 0;
+        let mut misses: Mask = 0;
+        for pos in FogGrid::all_positions() {
+            match fog.get(&pos) {
+                CellState::Hit => hits |= bit(&pos),
+                CellState::Miss => misses |= bit(&pos),
+                CellState::Unknown => {}
+            }
+        }
+        let mut ships: Vec<(ShipType, Vec<Mask>)> = ShipType::iter().map(|shiptype| {
+            let sunk_at = fog.get_sunk().iter().find(|(s, _)| *s == shiptype).map(|(_, pos)| bit(pos));
+            let open: Vec<Mask> = placements(shiptype.get_size() as i32).iter().copied()
+                .filter(|m| match sunk_at {
+                    Some(at) => m & at != 0 && m & !hits == 0,
+                    None => m & misses == 0 && (m & !hits != 0 || !fog.reports_sinks())
+                })
+                .collect();
+            (shiptype, open)
+        }).collect();
+        // Most constrained ships first keeps the search narrow.
+        ships.sort_by_key(|(_, open)| open.len());
+        Constraints{hits, ships}
+    }
+
+    pub fn solve<R: Rng>(&self, fog: &FogGrid, rng: &mut R) -> ProbabilityMap {
+        let constraints = Self::constraints(fog);
+        let space = constraints.ships.iter()
+            .try_fold(1u64, |n, (_, open)| n.checked_mul(open.len() as u64))
+            .unwrap_or(u64::MAX);
+        let mut counts = [0f64; 81];
+        let (total, layouts, exact) = if space <= self.exact_limit {
+            let layouts = Self::enumerate(&constraints, 0, 0, &mut counts);
+            (layouts as f64, layouts, true)
+        }
+        else {
+            let (total, layouts) = self.sample(&constraints, rng, &mut counts);
+            (total, layouts, false)
+        };
+
+        let mut cells = [[0f64; 9]; 9];
+        for pos in FogGrid::all_positions() {
+            let i = bit(&pos).trailing_zeros() as usize;
+            cells[pos.x as usize -1][pos.y as usize -1] = if total > 0.0 {counts[i] / total} else {0.0};
+        }
+        ProbabilityMap{cells, layouts, exact}
+    }
+
+    fn add(counts: &mut [f64; 81], mut occupied: Mask, weight: f64) {
+        while occupied != 0 {
+            counts[occupied.trailing_zeros() as usize] += weight;
+            occupied &= occupied - 1;
+        }
+    }
+
+    fn enumerate(constraints: &Constraints, ship: usize, occupied: Mask, counts: &mut [f64; 81]) -> u64 {
+        if ship == constraints.ships.len() {
+            if constraints.hits & !occupied != 0 {
+                return 0
+            }
+            Self::add(counts, occupied, 1.0);
+            return 1
+        }
+        // Give up early if the ships left cannot cover the hits left.
+        let room: u32 = constraints.ships[ship..].iter().map(|(s, _)| s.get_size() as u32).sum();
+        if (constraints.hits & !occupied).count_ones() > room {
+            return 0
+        }
+        let mut layouts = 0;
+        for m in constraints.ships[ship].1.iter() {
+            if m & occupied == 0 {
+                layouts += Self::enumerate(constraints, ship + 1, occupied | m, counts);
+            }
+        }
+        layouts
+    }
+
+    fn sample<R: Rng>(&self, constraints: &Constraints, rng: &mut R, counts: &mut [f64; 81]) -> (f64, u64) {
+        let mut total = 0.0;
+        let mut accepted = 0;
+        let mut open: Vec<Mask> = Vec::new();
+        for _ in 0..self.samples * 50 {
+            if accepted as usize >= self.samples {
+                break;
+            }
+            let mut occupied: Mask = 0;
+            let mut weight = 1.0;
+            for (_, placements) in constraints.ships.iter() {
+                open.clear();
+                open.extend(placements.iter().filter(|m| *m & occupied == 0));
+                if open.is_empty() {
+                    weight = 0.0;
+                    break;
+                }
+                weight *= open.len() as f64;
+                occupied |= open[rng.gen_range(0..open.len())];
+            }
+            if weight > 0.0 && constraints.hits & !occupied == 0 {
+                Self::add(counts, occupied, weight);
+                total += weight;
+                accepted += 1;
+            }
+        }
+        (total, accepted)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::ShotOutcome;
+
+    #[test]
+    fn empty_board_favours_the_centre() {
+        let fog = FogGrid::new();
+        let map = Solver{samples: 3000, exact_limit: 0}.solve(&fog, &mut StdRng::seed_from_u64(2));
+        assert!(!map.is_exact());
+        assert!(map.get(&Position::new(5,5)) > map.get(&Position::new(1,1)));
+        let total: f64 = FogGrid::all_positions().map(|pos| map.get(&pos)).sum();
+        assert!((total - 14.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn exact_solution_when_nearly_solved() {
+        // Everything but E5 and E6 is water apart from the sunk ships along rows 1 to 3.
+        let mut fog = FogGrid::new();
+        let sunk = [(ShipType::C5, 1, 5), (ShipType::H4, 2, 4), (ShipType::L3, 3, 3)];
+        for (shiptype, y, len) in sunk.iter() {
+            for x in 1..=*len {
+                let outcome = if x == *len {ShotOutcome::Sunk(*shiptype)} else {ShotOutcome::Hit};
+                fog.record(Position::new(x, *y), outcome);
+            }
+        }
+        for pos in FogGrid::all_positions() {
+            if fog.get(&pos) == CellState::Unknown && pos != Position::new(5,5) && pos != Position::new(5,6) && pos != Position::new(5,7) {
+                fog.record(pos, ShotOutcome::Miss);
+            }
+        }
+        fog.record(Position::new(5,6), ShotOutcome::Hit);
+        let map = Solver::default().solve(&fog, &mut StdRng::seed_from_u64(1));
+        assert!(map.is_exact());
+        assert_eq!(map.get_layouts(), 2);  // A2 at E5-E6 or E6-E7
+        assert!((map.get(&Position::new(5,5)) - 0.5).abs() < 1e-9);
+        assert!((map.get(&Position::new(5,6)) - 1.0).abs() < 1e-9);
+        assert_eq!(map.get(&Position::new(9,9)), 0.0);
+    }
+}
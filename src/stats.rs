@@ -0,0 +1,262 @@
+// Statistics of finished games, kept per player name.
+//
+// Games are appended to a JSON lines file, one game per line, so the
+// history survives server restarts and can be read by other tools.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+
+// Games that count as recent when showing trends.
+pub const RECENT_GAMES: usize = 10;
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GameStats {
+    pub player: String,
+    pub opponent: String,
+    pub won: bool,
+    pub finished_at: u64,  // Seconds since the Unix epoch
+    pub seconds: u64,
+    pub shots: u32,
+    pub hits: u32,
+    pub shots_to_first_hit: Option<u32>,
+    pub hunt_shots: u32,    // Fired with no damaged ship to finish off
+    pub hunt_hits: u32,
+    pub target_shots: u32,  // Fired while a damaged ship was still afloat
+    pub target_hits: u32,
+    pub hints: u32,
+}
+
+impl GameStats {
+    pub fn accuracy(&self) -> f64 {
+        ratio(self.hits, self.shots)
+    }
+}
+
+
+fn ratio(part: u32, whole: u32) -> f64 {
+    if whole == 0 {0.0} else {part as f64 / whole as f64}
+}
+
+
+// Follows one player's shots through a game.
+#[derive(Debug, Clone)]
+pub struct GameTracker {
+    started: Instant,
+    shots: u32,
+    hits: u32,
+    first_hit: Option<u32>,
+    hunt: (u32, u32),    // Shots and hits
+    target: (u32, u32),
+}
+
+impl Default for GameTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GameTracker {
+    pub fn new() -> GameTracker {
+        GameTracker{started: Instant::now(), shots: 0, hits: 0, first_hit: None, hunt: (0, 0), target: (0, 0)}
+    }
+
+    // Starts the clock again, for when firing begins after the fleets are placed.
+    pub fn restart(&mut self) {
+        self.started = Instant::now();
+    }
+
+    // `targeting` says whether a damaged ship was left to finish off when the shot was fired.
+    pub fn record(&mut self, targeting: bool, hit: bool) {
+        self.shots += 1;
+        let mode = if targeting {&mut self.target} else {&mut self.hunt};
+        mode.0 += 1;
+        if hit {
+            mode.1 += 1;
+            self.hits += 1;
+            self.first_hit.get_or_insert(self.shots);
+        }
+    }
+
+    pub fn finish(&self, player: &str, opponent: &str, won: bool, hints: u32) -> GameStats {
+        let finished_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        GameStats{
+            player: player.to_string(), opponent: opponent.to_string(), won, finished_at,
+            seconds: self.started.elapsed().as_secs(),
+            shots: self.shots, hits: self.hits, shots_to_first_hit: self.first_hit,
+            hunt_shots: self.hunt.0, hunt_hits: self.hunt.1,
+            target_shots: self.target.0, target_hits: self.target.1,
+            hints,
+        }
+    }
+}
+
+
+// Totals over a run of games.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Totals {
+    pub games: u32,
+    pub wins: u32,
+    pub shots: u32,
+    pub hits: u32,
+    pub seconds: u64,
+    pub hunt_shots: u32,
+    pub hunt_hits: u32,
+    pub target_shots: u32,
+    pub target_hits: u32,
+    first_hit_shots: u32,
+    first_hit_games: u32,
+}
+
+impl Totals {
+    fn of<'a>(games: impl Iterator<Item=&'a GameStats>) -> Totals {
+        let mut t = Totals::default();
+        for g in games {
+            t.games += 1;
+            t.wins += g.won as u32;
+            t.shots += g.shots;
+            t.hits += g.hits;
+            t.seconds += g.seconds;
+            t.hunt_shots += g.hunt_shots;
+            t.hunt_hits += g.hunt_hits;
+            t.target_shots += g.target_shots;
+            t.target_hits += g.target_hits;
+            if let Some(n) = g.shots_to_first_hit {
+                t.first_hit_shots += n;
+                t.first_hit_games += 1;
+            }
+        }
+        t
+    }
+
+    pub fn win_rate(&self) -> f64 {
+        ratio(self.wins, self.games)
+    }
+
+    pub fn accuracy(&self) -> f64 {
+        ratio(self.hits, self.shots)
+    }
+
+    pub fn hunt_accuracy(&self) -> f64 {
+        ratio(self.hunt_hits, self.hunt_shots)
+    }
+
+    pub fn target_accuracy(&self) -> f64 {
+        ratio(self.target_hits, self.target_shots)
+    }
+
+    pub fn mean_shots_to_first_hit(&self) -> f64 {
+        ratio(self.first_hit_shots, self.first_hit_games)
+    }
+
+    pub fn mean_seconds(&self) -> f64 {
+        if self.games == 0 {0.0} else {self.seconds as f64 / self.games as f64}
+    }
+}
+
+
+pub struct StatsStore {
+    path: Option<PathBuf>,  // None keeps everything in memory
+    games: Vec<GameStats>,
+}
+
+impl StatsStore {
+    pub fn in_memory() -> StatsStore {
+        StatsStore{path: None, games: Vec::new()}
+    }
+
+    // Loads the history from `path`, which is created on the first game if missing.
+    pub fn open(path: &Path) -> Result<StatsStore, String> {
+        let mut games = Vec::new();
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                for (i, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+                    let game = serde_json::from_str(line)
+                        .map_err(|e| format!("{} line {}: {}", path.display(), i+1, e))?;
+                    games.push(game);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("{}: {}", path.display(), e))
+        }
+        Ok(StatsStore{path: Some(path.to_path_buf()), games})
+    }
+
+    pub fn record(&mut self, game: GameStats) -> Result<(), String> {
+        if let Some(path) = &self.path {
+            let line = serde_json::to_string(&game).unwrap();
+            OpenOptions::new().create(true).append(true).open(path)
+                .and_then(|mut file| writeln!(file, "{}", line))
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        self.games.push(game);
+        Ok(())
+    }
+
+    pub fn get_games(&self, player: &str) -> Vec<&GameStats> {
+        self.games.iter().filter(|g| g.player == player).collect()
+    }
+
+    // Lifetime totals and those of the last RECENT_GAMES games.
+    pub fn summary(&self, player: &str) -> Option<(Totals, Totals)> {
+        let games = self.get_games(player);
+        if games.is_empty() {
+            return None
+        }
+        let recent = games.len().saturating_sub(RECENT_GAMES);
+        Some((Totals::of(games.iter().copied()), Totals::of(games[recent..].iter().copied())))
+    }
+
+    // One line answer to STATS.
+    pub fn report(&self, player: &str) -> String {
+        let (all, recent) = match self.summary(player) {
+            Some(summary) => summary,
+            None => return format!("No games recorded for {}.", player)
+        };
+        let trend = |now: f64, before: f64| format!("{:+.1}", 100.0 * (now - before));
+        format!("STATS {} games={} wins={} win_rate={:.1}% shots={} accuracy={:.1}% hunt={:.1}% target={:.1}% \
+                 first_hit={:.1} avg_time={:.0}s recent{}: win_rate={:.1}% ({}) accuracy={:.1}% ({})",
+                player, all.games, all.wins, 100.0 * all.win_rate(), all.shots, 100.0 * all.accuracy(),
+                100.0 * all.hunt_accuracy(), 100.0 * all.target_accuracy(),
+                all.mean_shots_to_first_hit(), all.mean_seconds(),
+                recent.games, 100.0 * recent.win_rate(), trend(recent.win_rate(), all.win_rate()),
+                100.0 * recent.accuracy(), trend(recent.accuracy(), all.accuracy()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn games_are_kept_per_player() {
+        let path = std::env::temp_dir().join(format!("battleship-stats-{}.jsonl", std::process::id()));
+        fs::remove_file(&path).ok();
+        let mut store = StatsStore::open(&path).unwrap();
+
+        let mut tracker = GameTracker::new();
+        for (targeting, hit) in [(false, false), (false, true), (true, true), (true, false)].iter() {
+            tracker.record(*targeting, *hit);
+        }
+        let game = tracker.finish("ann", "bob", true, 1);
+        assert_eq!(game.shots_to_first_hit, Some(2));
+        assert_eq!((game.hunt_shots, game.hunt_hits, game.target_shots, game.target_hits), (2, 1, 2, 1));
+        store.record(game.clone()).unwrap();
+        store.record(GameTracker::new().finish("bob", "ann", false, 0)).unwrap();
+
+        // The history is read back from disk.
+        let store = StatsStore::open(&path).unwrap();
+        assert_eq!(store.get_games("ann"), vec![&game]);
+        let (all, _) = store.summary("ann").unwrap();
+        assert_eq!((all.games, all.wins, all.shots, all.hits), (1, 1, 4, 2));
+        assert!(store.report("ann").starts_with("STATS ann games=1 wins=1 win_rate=100.0% shots=4 accuracy=50.0%"));
+        assert_eq!(store.report("cid"), "No games recorded for cid.");
+        fs::remove_file(&path).ok();
+    }
+}
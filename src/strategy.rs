@@ -0,0 +1,278 @@
+// Computer players' shooting strategies, and the fog-of-war view of the
+// opponent's fleet they work from.
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use strum::IntoEnumIterator;
+
+use crate::{BoardConfig, Position, ShipType, ShotOutcome};
+use crate::engine::EngineStrategy;
+use crate::solver::Solver;
+
+
+#[derive(Hash, Eq, PartialEq, Debug, Copy, Clone)]
+pub enum CellState {
+    Unknown,
+    Miss,
+    Hit
+}
+
+
+// Everything a shooter has learned about the opponent's fleet: which cells
+// were hits or misses, and which ship went down on which shot. The cells of a
+// sunk ship are not revealed, only that the sinking shot was one of them.
+#[derive(Clone, Debug)]
+pub struct FogGrid {
+    cells: [[CellState; 9]; 9],  // Indexed [x-1][y-1], like Board::get_contents
+    sunk: Vec<(ShipType, Position)>,
+    shots: u32,
+    sinks_reported: bool,  // False when shots only ever come back as hit or miss
+}
+
+impl Default for FogGrid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FogGrid {
+    pub fn new() -> FogGrid {
+        FogGrid{cells: [[CellState::Unknown; 9]; 9], sunk: Vec::new(), shots: 0, sinks_reported: true}
+    }
+
+    // What a player of the network game knows, from Board::get_fog_of_war.
+    // The server only ever answers HIT or MISS, so nothing is known of sinkings.
+    pub fn from_fog_of_war(fog: &[[char; 9]; 9]) -> FogGrid {
+        let mut grid = FogGrid::new();
+        grid.sinks_reported = false;
+        for pos in Self::all_positions() {
+            match fog[pos.x as usize -1][pos.y as usize -1] {
+                'X' => grid.record(pos, ShotOutcome::Hit),
+                'o' => grid.record(pos, ShotOutcome::Miss),
+                _ => {}
+            }
+        }
+        grid
+    }
+
+    pub fn all_positions() -> impl Iterator<Item=Position> {
+        (1..=9).flat_map(|y| (1..=9).map(move |x| Position::new(x,y)))
+    }
+
+    pub fn contains(pos: &Position) -> bool {
+        (1..=9).contains(&pos.x) && (1..=9).contains(&pos.y)
+    }
+
+    pub fn get(&self, pos: &Position) -> CellState {
+        if Self::contains(pos) {
+            self.cells[pos.x as usize -1][pos.y as usize -1]
+        }
+        else {
+            CellState::Miss  // Nothing to find off the board
+        }
+    }
+
+    pub fn record(&mut self, pos: Position, outcome: ShotOutcome) {
+        self.shots += 1;
+        if !Self::contains(&pos) {
+            return
+        }
+        let state = &mut self.cells[pos.x as usize -1][pos.y as usize -1];
+        match outcome {
+            ShotOutcome::Miss => {
+                // Firing at a known hit again is reported as a miss; it stays a hit.
+                if *state == CellState::Unknown {
+                    *state = CellState::Miss;
+                }
+            }
+            ShotOutcome::Hit => *state = CellState::Hit,
+            ShotOutcome::Sunk(shiptype) => {
+                *state = CellState::Hit;
+                self.sunk.push((shiptype, pos));
+            }
+        }
+    }
+
+    pub fn get_shots(&self) -> u32 {
+        self.shots
+    }
+
+    pub fn get_sunk(&self) -> &[(ShipType, Position)] {
+        &self.sunk
+    }
+
+    pub fn reports_sinks(&self) -> bool {
+        self.sinks_reported
+    }
+
+    pub fn remaining_ships(&self) -> Vec<ShipType> {
+        ShipType::iter().filter(|s| !self.sunk.iter().any(|(sunk, _)| sunk == s)).collect()
+    }
+
+    pub fn untried(&self) -> Vec<Position> {
+        Self::all_positions().filter(|pos| self.get(pos) == CellState::Unknown).collect()
+    }
+
+    pub fn hits(&self) -> Vec<Position> {
+        Self::all_positions().filter(|pos| self.get(pos) == CellState::Hit).collect()
+    }
+
+    // Hits that cannot all be accounted for by the ships sunk so far.
+    pub fn unresolved_hits(&self) -> usize {
+        let sunk_cells: usize = self.sunk.iter().map(|(s, _)| s.get_size() as usize).sum();
+        self.hits().len().saturating_sub(sunk_cells)
+    }
+}
+
+
+pub trait Strategy: Send {
+    fn name(&self) -> String;
+
+    // Picks the next cell to fire at. Should be an untried cell while there are any.
+    fn next_shot(&mut self, fog: &FogGrid, rng: &mut StdRng) -> Position;
+
+    // Told the outcome of every shot, for strategies that keep their own state.
+    fn observe(&mut self, pos: Position, outcome: ShotOutcome) {}
+
+    // How this player sets up its own fleet in two sided games.
+    fn placement(&mut self, rng: &mut StdRng) -> BoardConfig {
+        BoardConfig::Auto
+    }
+
+    // Why the player broke the rules (see engine), if it did. It loses the game on the spot.
+    fn get_failure(&self) -> Option<&str> {
+        None
+    }
+
+    fn has_forfeited(&self) -> bool {
+        self.get_failure().is_some()
+    }
+}
+
+
+// Fires at untried cells uniformly at random.
+pub struct RandomStrategy;
+
+impl Strategy for RandomStrategy {
+    fn name(&self) -> String {
+        String::from("random")
+    }
+
+    fn next_shot(&mut self, fog: &FogGrid, rng: &mut StdRng) -> Position {
+        *fog.untried().choose(rng).unwrap_or(&Position::new(1,1))
+    }
+}
+
+
+// Hunts on a checkerboard (every ship covers at least one black square),
+// then once something is hit works along the line of hits until it sinks.
+pub struct HuntTargetStrategy;
+
+impl HuntTargetStrategy {
+    const STEPS: [(i32, i32); 4] = [(0,-1), (0,1), (-1,0), (1,0)];
+
+    fn target_score(fog: &FogGrid, pos: &Position) -> u32 {
+        let mut score = 0;
+        for (dx, dy) in Self::STEPS.iter() {
+            let next = Position::new(pos.x + dx, pos.y + dy);
+            if fog.get(&next) == CellState::Hit {
+                score += 1;
+                // A second hit further along means we are extending a line.
+                if fog.get(&Position::new(pos.x + 2*dx, pos.y + 2*dy)) == CellState::Hit {
+                    score += 2;
+                }
+            }
+        }
+        score
+    }
+}
+
+impl Strategy for HuntTargetStrategy {
+    fn name(&self) -> String {
+        String::from("hunt-target")
+    }
+
+    fn next_shot(&mut self, fog: &FogGrid, rng: &mut StdRng) -> Position {
+        let untried = fog.untried();
+        if fog.unresolved_hits() > 0 {
+            let best = untried.iter().map(|pos| Self::target_score(fog, pos)).max().unwrap_or(0);
+            if best > 0 {
+                let targets: Vec<&Position> = untried.iter()
+                    .filter(|pos| Self::target_score(fog, pos) == best)
+                    .collect();
+                return **targets.choose(rng).unwrap()
+            }
+        }
+        let hunting: Vec<&Position> = untried.iter().filter(|pos| (pos.x + pos.y) % 2 == 0).collect();
+        match hunting.choose(rng) {
+            Some(pos) => **pos,
+            None => *untried.choose(rng).unwrap_or(&Position::new(1,1))
+        }
+    }
+}
+
+
+// Fires wherever the solver thinks a ship is most likely to be.
+pub struct MonteCarloStrategy {
+    solver: Solver,
+}
+
+impl MonteCarloStrategy {
+    pub fn new(solver: Solver) -> MonteCarloStrategy {
+        MonteCarloStrategy{solver}
+    }
+}
+
+impl Strategy for MonteCarloStrategy {
+    fn name(&self) -> String {
+        String::from("montecarlo")
+    }
+
+    fn next_shot(&mut self, fog: &FogGrid, rng: &mut StdRng) -> Position {
+        let ranked = self.solver.solve(fog, rng).ranked(fog);
+        let best = match ranked.first() {
+            Some((_, p)) => *p,
+            None => return Position::new(1,1)
+        };
+        let ties: Vec<Position> = ranked.iter().take_while(|(_, p)| *p >= best).map(|(pos, _)| *pos).collect();
+        *ties.choose(rng).unwrap()
+    }
+}
+
+
+pub const STRATEGY_NAMES: [&str; 4] = ["random", "hunt-target", "montecarlo", "engine:<command>"];
+
+pub fn strategy_by_name(name: &str) -> Result<Box<dyn Strategy>, String> {
+    if let Some(command) = name.strip_prefix("engine:") {
+        return Ok(Box::new(EngineStrategy::spawn(command)?))
+    }
+    match name {
+        "random" => Ok(Box::new(RandomStrategy)),
+        "hunt-target" => Ok(Box::new(HuntTargetStrategy)),
+        "montecarlo" => Ok(Box::new(MonteCarloStrategy::new(Solver::default()))),
+        _ => Err(format!("Unknown strategy {} (expected one of: {})", name, STRATEGY_NAMES.join(", ")))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn hunt_target_follows_up_hits() {
+        let mut fog = FogGrid::new();
+        let mut rng = StdRng::seed_from_u64(1);
+        fog.record(Position::new(5,5), ShotOutcome::Hit);
+        fog.record(Position::new(6,5), ShotOutcome::Hit);
+        let shot = HuntTargetStrategy.next_shot(&fog, &mut rng);
+        assert!(shot == Position::new(4,5) || shot == Position::new(7,5));
+
+        fog.record(Position::new(7,5), ShotOutcome::Sunk(ShipType::L3));
+        assert_eq!(fog.unresolved_hits(), 0);
+        assert_eq!(fog.remaining_ships(), vec![ShipType::C5, ShipType::H4, ShipType::A2]);
+        let shot = HuntTargetStrategy.next_shot(&fog, &mut rng);
+        assert_eq!((shot.x + shot.y) % 2, 0);
+    }
+}
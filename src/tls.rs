@@ -0,0 +1,314 @@
+// Optional TLS for the connection between client and server.
+//
+// Both ends read and write the same connection from two threads (see
+// server::connection_handler and the client's reader thread), so the TLS
+// session is shared behind a lock and only the raw socket is cloned. Reading
+// waits on the socket without holding the lock, so a reader waiting for the
+// next line never holds up a writer.
+//
+// The client trusts nothing but the CA it is given, so a self-signed
+// certificate works as well as one from a real CA.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rustls::{ClientConfig, ClientConnection, Connection, RootCertStore, ServerConfig, ServerConnection};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+// What the server and client need of a connection, plain or encrypted.
+pub trait Stream: Read + Write + Send + Sized + 'static {
+    const TRANSPORT: &'static str;  // For the log
+    fn try_clone(&self) -> io::Result<Self>;
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+    fn shutdown(&self);
+    // Makes a read blocked in another thread return end of file.
+    fn stop_reading(&self);
+}
+
+impl Stream for TcpStream {
+    const TRANSPORT: &'static str = "tcp";
+
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn shutdown(&self) {
+        TcpStream::shutdown(self, Shutdown::Both).ok();
+    }
+
+    fn stop_reading(&self) {
+        TcpStream::shutdown(self, Shutdown::Read).ok();
+    }
+}
+
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let certs: Vec<CertificateDer> = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates found", path.display()))
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("{}: {}", path.display(), e))?
+        .ok_or_else(|| format!("{}: no private key found", path.display()))
+}
+
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+
+// Server side: the certificate chain (PEM, server first) and its private key.
+pub fn server_config(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>, String> {
+    let config = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(load_certs(cert)?, load_key(key)?)
+        .map_err(|e| format!("{}: {}", cert.display(), e))?;
+    Ok(Arc::new(config))
+}
+
+// Client side: only servers with a certificate signed by the CA in `ca` are accepted.
+pub fn client_config(ca: &Path) -> Result<Arc<ClientConfig>, String> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca)? {
+        roots.add(cert).map_err(|e| format!("{}: {}", ca.display(), e))?;
+    }
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+
+pub struct TlsStream {
+    conn: Arc<Mutex<Connection>>,
+    sock: TcpStream,
+}
+
+impl TlsStream {
+    // Completes the handshake before returning, so a client that never
+    // finishes it is dropped after HANDSHAKE_TIMEOUT instead of holding on.
+    pub fn accept(sock: TcpStream, config: Arc<ServerConfig>) -> io::Result<TlsStream> {
+        let mut conn: Connection = ServerConnection::new(config).map_err(io::Error::other)?.into();
+        sock.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut &sock)?;
+        }
+        sock.set_read_timeout(None)?;
+        Ok(TlsStream{conn: Arc::new(Mutex::new(conn)), sock})
+    }
+
+    pub fn connect(sock: TcpStream, server_name: &str, config: Arc<ClientConfig>) -> io::Result<TlsStream> {
+        let name = ServerName::try_from(server_name.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let conn = ClientConnection::new(config, name).map_err(io::Error::other)?;
+        let mut stream = TlsStream{conn: Arc::new(Mutex::new(conn.into())), sock};
+        stream.flush()?;  // Sends the client hello
+        Ok(stream)
+    }
+
+    // Sends whatever the session has queued up: handshake messages or encrypted data.
+    fn send_tls(&self, conn: &mut Connection) -> io::Result<()> {
+        while conn.wants_write() {
+            conn.write_tls(&mut &self.sock)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut raw = [0u8; 4096];
+        loop {
+            {
+                let mut conn = self.conn.lock().unwrap();
+                match conn.reader().read(buf) {
+                    Ok(n) => return Ok(n),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e)
+                }
+            }
+            // Nothing decrypted yet: wait for more from the other end.
+            let n = (&self.sock).read(&mut raw)?;
+            if n == 0 {
+                return Ok(0)
+            }
+            let mut conn = self.conn.lock().unwrap();
+            let mut data = &raw[..n];
+            while !data.is_empty() {
+                conn.read_tls(&mut data)?;
+                conn.process_new_packets().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            }
+            self.send_tls(&mut conn)?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let n = conn.writer().write(buf)?;
+        self.send_tls(&mut conn)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        conn.writer().flush()?;
+        self.send_tls(&mut conn)
+    }
+}
+
+impl Stream for TlsStream {
+    const TRANSPORT: &'static str = "tls";
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(TlsStream{conn: self.conn.clone(), sock: self.sock.try_clone()?})
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.sock.peer_addr()
+    }
+
+    fn shutdown(&self) {
+        let mut conn = self.conn.lock().unwrap();
+        conn.send_close_notify();
+        self.send_tls(&mut conn).ok();
+        self.sock.shutdown(Shutdown::Both).ok();
+    }
+
+    fn stop_reading(&self) {
+        self.sock.shutdown(Shutdown::Read).ok();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::thread;
+    use crate::config::Config;
+    use crate::limits::Limits;
+    use crate::server::{self, Lobby};
+
+    // A CA and a certificate it signed for localhost, written out as PEM files.
+    fn certificates(dir: &Path) -> (PathBuf, PathBuf, PathBuf) {
+        let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec![String::from("localhost")]).unwrap()
+            .signed_by(&key, &ca, &ca_key).unwrap();
+
+        let paths = (dir.join("ca.pem"), dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&paths.0, ca.pem()).unwrap();
+        std::fs::write(&paths.1, cert.pem()).unwrap();
+        std::fs::write(&paths.2, key.serialize_pem()).unwrap();
+        paths
+    }
+
+    #[test]
+    fn lines_cross_a_pinned_connection() {
+        let dir = std::env::temp_dir().join(format!("battleship-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (ca, cert, key) = certificates(&dir);
+        let server = server_config(&cert, &key).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let echo = thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let stream = TlsStream::accept(sock, server).unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            writer.write_all(format!("echo {}", line).as_bytes()).unwrap();
+        });
+
+        let sock = TcpStream::connect(addr).unwrap();
+        let mut stream = TlsStream::connect(sock, "localhost", client_config(&ca).unwrap()).unwrap();
+        stream.write_all(b"hello\n").unwrap();
+        let mut line = String::new();
+        BufReader::new(stream.try_clone().unwrap()).read_line(&mut line).unwrap();
+        assert_eq!(line, "echo hello\n");
+        echo.join().unwrap();
+
+        // A client pinned to another CA refuses the server.
+        std::fs::create_dir_all(dir.join("other")).unwrap();
+        let (other, _, _) = certificates(&dir.join("other"));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = server_config(&cert, &key).unwrap();
+        thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            assert!(TlsStream::accept(sock, server).is_err());
+        });
+        let sock = TcpStream::connect(addr).unwrap();
+        let mut stream = TlsStream::connect(sock, "localhost", client_config(&other).unwrap()).unwrap();
+        stream.write_all(b"hello\n").ok();
+        assert!(stream.read(&mut [0u8; 16]).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn handshakes_come_first() {
+        let dir = std::env::temp_dir().join(format!("battleship-tls-refused-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (ca, cert, key) = certificates(&dir);
+        let lobby = Lobby::shared();
+        let limits = Limits{max_connections_per_ip: 1, ..Limits::default()};
+        lobby.lock().unwrap().apply_config(&Config{limits, ..Config::default()});
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = server_config(&cert, &key).unwrap();
+        thread::spawn(move || server::serve_tls(listener, lobby, server));
+
+        // A client that never says hello is dropped without taking up a place...
+        let mut silent = TcpStream::connect(addr).unwrap();
+        silent.set_read_timeout(Some(HANDSHAKE_TIMEOUT * 2)).unwrap();
+        assert_eq!(silent.read(&mut [0u8; 1]).unwrap(), 0);
+
+        // ...and one turned away is told so over TLS.
+        let connect = || {
+            let sock = TcpStream::connect(addr).unwrap();
+            BufReader::new(TlsStream::connect(sock, "localhost", client_config(&ca).unwrap()).unwrap())
+        };
+        let mut first = connect();
+        first.get_mut().write_all(b"STATS\n").unwrap();
+        let mut line = String::new();
+        first.read_line(&mut line).unwrap();
+        assert_eq!(line, "Not logged in. Use LOGIN <name> <password> first.\n");
+        line.clear();
+        connect().read_line(&mut line).unwrap();
+        assert_eq!(line, "Too many connections from 127.0.0.1 (at most 1).\n");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,332 @@
+// Computer players against each other under the full two sided rules:
+// each sets up its own fleet, then they take turns firing and the first to
+// sink the other's fleet wins. Entrants play round robin or Swiss, and are
+// ranked by Elo with 95% confidence intervals.
+
+use std::fmt;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::{Board, ShotOutcome};
+use crate::simulate::game_seed;
+use crate::strategy::{FogGrid, Strategy};
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    RoundRobin,
+    Swiss(usize),  // Number of rounds
+}
+
+
+pub struct TournamentConfig {
+    pub format: Format,
+    pub matches: usize,  // Games per pairing
+    pub seed: u64,
+}
+
+impl Default for TournamentConfig {
+    fn default() -> Self {
+        TournamentConfig{format: Format::RoundRobin, matches: 20, seed: 0}
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MatchResult {
+    Win(usize),  // Index of the winning player
+    Draw,
+}
+
+
+// Sets up a player's fleet; an illegal placement forfeits the game.
+fn setup_fleet(player: &mut dyn Strategy, rng: &mut StdRng) -> Option<Board> {
+    let mut board = Board::new();
+    let config = player.placement(rng);
+    if !config.validate() || board.setup_with_rng(config, rng).is_err() || !board.get_unplaced_ships().is_empty() {
+        return None
+    }
+    Some(board)
+}
+
+
+// One game between two players, players[0] firing first. Nobody wins if both
+// are still afloat after every cell has been tried a few times over.
+pub fn play_match(players: [&mut dyn Strategy; 2], rng: &mut StdRng) -> MatchResult {
+    let boards = [setup_fleet(players[0], rng), setup_fleet(players[1], rng)];
+    let mut boards = match boards {
+        [Some(a), Some(b)] => [a, b],
+        [None, Some(_)] => return MatchResult::Win(1),
+        [Some(_), None] => return MatchResult::Win(0),
+        [None, None] => return MatchResult::Draw
+    };
+    let mut fogs = [FogGrid::new(), FogGrid::new()];
+
+    for turn in 0..(2 * 81 * 4) {
+        let side = turn % 2;
+        let target = 1 - side;
+        let pos = players[side].next_shot(&fogs[side], rng);
+        if players[side].has_forfeited() {
+            return MatchResult::Win(target)
+        }
+        let outcome = boards[target].fire(pos);
+        fogs[side].record(pos, outcome);
+        players[side].observe(pos, outcome);
+        if outcome != ShotOutcome::Miss && boards[target].is_game_complete() {
+            return MatchResult::Win(side)
+        }
+    }
+    MatchResult::Draw
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Standing {
+    pub name: String,
+    pub games: u32,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    pub elo: f64,
+}
+
+impl Standing {
+    fn new(name: String) -> Standing {
+        Standing{name, games: 0, wins: 0, draws: 0, losses: 0, elo: Elo::INITIAL}
+    }
+
+    pub fn score(&self) -> f64 {
+        self.wins as f64 + 0.5 * self.draws as f64
+    }
+
+    pub fn win_rate(&self) -> f64 {
+        if self.games == 0 {0.0} else {self.score() / self.games as f64}
+    }
+
+    // 95% Wilson score interval for the win rate.
+    pub fn win_rate_interval(&self) -> (f64, f64) {
+        wilson_interval(self.score(), self.games as f64)
+    }
+
+    // 95% interval for the rating: how far the rating would move if the true
+    // win rate were at either end of its interval.
+    pub fn elo_interval(&self) -> (f64, f64) {
+        if self.games == 0 {
+            return (self.elo, self.elo)
+        }
+        let p = Elo::difference(self.win_rate());
+        let (lo, hi) = self.win_rate_interval();
+        (self.elo + Elo::difference(lo) - p, self.elo + Elo::difference(hi) - p)
+    }
+}
+
+
+pub struct Elo;
+
+impl Elo {
+    pub const INITIAL: f64 = 1500.0;
+    pub const K: f64 = 16.0;
+
+    pub fn expected(rating: f64, opponent: f64) -> f64 {
+        1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0))
+    }
+
+    // Rating difference that gives the expected score `p`, capped at +-800.
+    pub fn difference(p: f64) -> f64 {
+        let p = p.clamp(0.01, 0.99);
+        400.0 * (p / (1.0 - p)).log10()
+    }
+}
+
+
+pub fn wilson_interval(successes: f64, n: f64) -> (f64, f64) {
+    if n <= 0.0 {
+        return (0.0, 1.0)
+    }
+    let z = 1.96;
+    let p = successes / n;
+    let denom = 1.0 + z*z / n;
+    let centre = (p + z*z / (2.0*n)) / denom;
+    let spread = z * (p*(1.0 - p)/n + z*z/(4.0*n*n)).sqrt() / denom;
+    ((centre - spread).max(0.0), (centre + spread).min(1.0))
+}
+
+
+pub struct TournamentReport {
+    pub format: Format,
+    pub matches: usize,
+    pub seed: u64,
+    pub standings: Vec<Standing>,  // Best first
+    pub failures: Vec<String>,     // Why each forfeited game was forfeited, in game order
+}
+
+impl fmt::Display for TournamentReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let format = match self.format {
+            Format::RoundRobin => String::from("round robin"),
+            Format::Swiss(rounds) => format!("Swiss, {} rounds", rounds)
+        };
+        writeln!(f, "Tournament: {}, {} games per pairing, seed {}", format, self.matches, self.seed)?;
+        writeln!(f, "{:>4}  {:<24} {:>6} {:>6} {:>6} {:>6}  {:>7} {:>15}  {:>6} {:>13}",
+                 "Rank", "Player", "Games", "Wins", "Draws", "Losses", "Win %", "95% CI", "Elo", "95% CI")?;
+        for (rank, s) in self.standings.iter().enumerate() {
+            let (wlo, whi) = s.win_rate_interval();
+            let (elo_lo, elo_hi) = s.elo_interval();
+            writeln!(f, "{:>4}  {:<24} {:>6} {:>6} {:>6} {:>6}  {:>6.1}% {:>6.1}% - {:>5.1}%  {:>6.0} {:>6.0} - {:<5.0}",
+                     rank+1, s.name, s.games, s.wins, s.draws, s.losses,
+                     100.0*s.win_rate(), 100.0*wlo, 100.0*whi, s.elo, elo_lo, elo_hi)?;
+        }
+        Ok(())
+    }
+}
+
+
+pub struct Tournament<F> where F: Fn(&str) -> Result<Box<dyn Strategy>, String> {
+    entrants: Vec<String>,
+    make: F,
+    config: TournamentConfig,
+    standings: Vec<Standing>,
+    played: Vec<(usize, usize)>,
+    games: u64,
+    failures: Vec<String>,
+}
+
+impl<F> Tournament<F> where F: Fn(&str) -> Result<Box<dyn Strategy>, String> {
+    // `make` builds a fresh player from an entrant's name for every game.
+    pub fn new(entrants: Vec<String>, make: F, config: TournamentConfig) -> Result<Tournament<F>, String> {
+        if entrants.len() < 2 {
+            return Err(String::from("A tournament needs at least two players"))
+        }
+        let mut standings = Vec::new();
+        for name in entrants.iter() {
+            standings.push(Standing::new(make(name)?.name()));
+        }
+        Ok(Tournament{entrants, make, config, standings, played: Vec::new(), games: 0, failures: Vec::new()})
+    }
+
+    // Plays all games between two entrants, alternating who fires first.
+    fn play_pairing(&mut self, a: usize, b: usize) -> Result<(), String> {
+        for game in 0..self.config.matches {
+            let mut rng = StdRng::seed_from_u64(game_seed(self.config.seed, self.games));
+            self.games += 1;
+            let (first, second) = if game % 2 == 0 {(a, b)} else {(b, a)};
+            let mut p1 = (self.make)(&self.entrants[first])?;
+            let mut p2 = (self.make)(&self.entrants[second])?;
+            let result = match play_match([p1.as_mut(), p2.as_mut()], &mut rng) {
+                MatchResult::Win(0) => MatchResult::Win(first),
+                MatchResult::Win(_) => MatchResult::Win(second),
+                MatchResult::Draw => MatchResult::Draw
+            };
+            for player in [&p1, &p2] {
+                if let Some(reason) = player.get_failure() {
+                    self.failures.push(format!("game {}: {}", self.games - 1, reason));
+                }
+            }
+            self.record(a, b, result);
+        }
+        self.played.push((a, b));
+        Ok(())
+    }
+
+    fn record(&mut self, a: usize, b: usize, result: MatchResult) {
+        let score_a = match result {
+            MatchResult::Win(w) if w == a => 1.0,
+            MatchResult::Win(_) => 0.0,
+            MatchResult::Draw => 0.5
+        };
+        let (elo_a, elo_b) = (self.standings[a].elo, self.standings[b].elo);
+        let expected_a = Elo::expected(elo_a, elo_b);
+        for (i, score) in [(a, score_a), (b, 1.0 - score_a)].iter() {
+            let s = &mut self.standings[*i];
+            s.games += 1;
+            match *score {
+                x if x > 0.75 => s.wins += 1,
+                x if x < 0.25 => s.losses += 1,
+                _ => s.draws += 1
+            }
+        }
+        self.standings[a].elo += Elo::K * (score_a - expected_a);
+        self.standings[b].elo += Elo::K * ((1.0 - score_a) - (1.0 - expected_a));
+    }
+
+    fn have_played(&self, a: usize, b: usize) -> bool {
+        self.played.iter().any(|p| *p == (a, b) || *p == (b, a))
+    }
+
+    // Pairs players with similar scores, avoiding rematches where possible.
+    // With an odd number of players the lowest one left over sits out the round.
+    fn swiss_pairings(&self) -> Vec<(usize, usize)> {
+        let mut order: Vec<usize> = (0..self.standings.len()).collect();
+        order.sort_by(|a, b| {
+            let (sa, sb) = (&self.standings[*a], &self.standings[*b]);
+            sb.score().partial_cmp(&sa.score()).unwrap()
+                .then(sb.elo.partial_cmp(&sa.elo).unwrap())
+                .then(a.cmp(b))
+        });
+        let mut pairs = Vec::new();
+        while order.len() >= 2 {
+            let a = order.remove(0);
+            let pick = order.iter().position(|b| !self.have_played(a, *b)).unwrap_or(0);
+            pairs.push((a, order.remove(pick)));
+        }
+        pairs
+    }
+
+    pub fn run(mut self) -> Result<TournamentReport, String> {
+        match self.config.format {
+            Format::RoundRobin => {
+                for a in 0..self.entrants.len() {
+                    for b in (a+1)..self.entrants.len() {
+                        self.play_pairing(a, b)?;
+                    }
+                }
+            }
+            Format::Swiss(rounds) => {
+                for _ in 0..rounds {
+                    for (a, b) in self.swiss_pairings() {
+                        self.play_pairing(a, b)?;
+                    }
+                }
+            }
+        }
+        let mut standings = self.standings;
+        standings.sort_by(|a, b| b.elo.partial_cmp(&a.elo).unwrap().then(a.name.cmp(&b.name)));
+        Ok(TournamentReport{format: self.config.format, matches: self.config.matches, seed: self.config.seed, standings,
+                            failures: self.failures})
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategy::strategy_by_name;
+
+    #[test]
+    fn stronger_strategy_wins_round_robin() {
+        let entrants = vec![String::from("random"), String::from("hunt-target")];
+        let config = TournamentConfig{format: Format::RoundRobin, matches: 40, seed: 3};
+        let report = Tournament::new(entrants, strategy_by_name, config).unwrap().run().unwrap();
+        assert_eq!(report.standings[0].name, "hunt-target");
+        assert_eq!(report.standings[0].games, 40);
+        assert_eq!(report.standings[0].wins, report.standings[1].losses);
+        assert!(report.standings[0].elo > Elo::INITIAL);
+        assert!(report.to_string().contains("hunt-target"));
+    }
+
+    #[test]
+    fn swiss_rounds_pair_everybody() {
+        let entrants: Vec<String> = ["random", "hunt-target", "random", "hunt-target"].iter().map(|s| s.to_string()).collect();
+        let config = TournamentConfig{format: Format::Swiss(3), matches: 2, seed: 1};
+        let report = Tournament::new(entrants, strategy_by_name, config).unwrap().run().unwrap();
+        assert!(report.standings.iter().all(|s| s.games == 6));
+    }
+
+    #[test]
+    fn wilson_interval_bounds() {
+        let (lo, hi) = wilson_interval(50.0, 100.0);
+        assert!(lo > 0.39 && lo < 0.41 && hi > 0.59 && hi < 0.61);
+        assert_eq!(wilson_interval(0.0, 0.0), (0.0, 1.0));
+    }
+}
@@ -0,0 +1,193 @@
+// WebSocket gateway, so browsers can play on the same lobby as TCP clients.
+//
+// Each text message from the browser is one command line, and every reply or
+// pushed event goes back as one text message, exactly as the line protocol
+// would have written it (see the top of server.rs).
+//
+// A WebSocket cannot be split between a reader and a writer thread, so one
+// thread does both: it waits for the browser with a short timeout and sends
+// whatever the lobby has queued in between.
+//
+// The limits of limits.rs apply as on TCP, a message counting as a line.
+
+use std::io::{Error, ErrorKind};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use tungstenite::error::CapacityError;
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::{Message, WebSocket};
+
+use crate::log_event;
+use crate::metrics::METRICS;
+use crate::server::{Admission, ClientHandle, SharedLobby, Session};
+use crate::tls::Stream;
+
+
+// How long to wait for the browser before looking for events to push.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// How long a browser gets to finish the opening handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+fn ws_error(e: tungstenite::Error) -> Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        e => Error::new(ErrorKind::InvalidData, e)
+    }
+}
+
+fn timed_out(e: &tungstenite::Error) -> bool {
+    matches!(e, tungstenite::Error::Io(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut)
+}
+
+
+pub fn connection_handler(stream: TcpStream, lobby: SharedLobby) -> Result<(), Error> {
+    let peer = stream.peer_addr()?;
+    // Counted before the handshake, which a refused address does not get to start.
+    let _admission = match Admission::new(&lobby, peer.ip()) {
+        Ok(admission) => admission,
+        Err(reason) => {
+            log_event!(Warn, "refused", peer = peer, reason = reason);
+            stream.shutdown(Shutdown::Both).ok();
+            return Ok(())
+        }
+    };
+    let max_line = lobby.lock().unwrap().get_limits().max_line_length;
+    let config = WebSocketConfig{max_message_size: Some(max_line), max_frame_size: Some(max_line), ..Default::default()};
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut socket = tungstenite::accept_with_config(stream, Some(config))
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+    let (outbox, inbox) = mpsc::channel();
+    let mut session = Session::new(lobby.clone(), outbox);
+    let closer = socket.get_ref().try_clone()?;
+    let handle = ClientHandle::new(peer, move || closer.stop_reading());
+    lobby.lock().unwrap().set_handle(session.get_conn(), handle);
+    METRICS.connection_opened();
+    log_event!(Info, "connect", conn = session.get_conn(), peer = peer, transport = "ws");
+
+    let result = serve_socket(&mut socket, &mut session, &inbox);
+    log_event!(Info, "disconnect", conn = session.get_conn(), peer = peer);
+    session.close();
+    drop(session);
+    // Whatever was queued before leaving, like the end of a game, still goes out.
+    for msg in inbox.try_iter() {
+        socket.send(Message::Text(msg)).ok();
+    }
+    socket.close(None).ok();
+    socket.flush().ok();
+    result
+}
+
+fn serve_socket(socket: &mut WebSocket<TcpStream>, session: &mut Session, inbox: &Receiver<String>) -> Result<(), Error> {
+    loop {
+        for msg in inbox.try_iter() {
+            socket.write(Message::Text(msg)).map_err(ws_error)?;
+        }
+        match socket.flush() {
+            Ok(()) => {}
+            Err(e) if timed_out(&e) => {}
+            Err(e) => return Err(ws_error(e))
+        }
+
+        let line = match socket.read() {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Binary(bytes)) => String::from_utf8_lossy(&bytes).into_owned(),
+            Ok(Message::Close(_)) | Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Ok(_) => continue,  // Pings are answered by tungstenite
+            Err(e) if timed_out(&e) => continue,
+            Err(tungstenite::Error::Capacity(CapacityError::MessageTooLong{max_size, ..})) => {
+                session.reply(&format!("Line too long (at most {} bytes).", max_size));
+                return Ok(())
+            }
+            Err(e) => return Err(ws_error(e))
+        };
+        let line = line.trim();
+        if !session.handle_line(line) {
+            return Ok(())
+        }
+    }
+}
+
+
+pub fn serve(listener: TcpListener, lobby: SharedLobby) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let lobby = lobby.clone();
+                thread::spawn(move || {
+                    if let Err(e) = connection_handler(stream, lobby) {
+                        log_event!(Warn, "connection_failed", error = e);
+                    }
+                });
+            }
+            Err(e) => log_event!(Warn, "accept_failed", error = e)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use crate::config::Config;
+    use crate::limits::Limits;
+    use crate::server::{self, Lobby};
+
+    fn text(socket: &mut WebSocket<TcpStream>) -> String {
+        match socket.read().unwrap() {
+            Message::Text(text) => text,
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+
+    #[test]
+    fn browsers_share_games_with_tcp_clients() {
+        let lobby = Lobby::shared();
+        let ws_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let ws_addr = ws_listener.local_addr().unwrap();
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp_addr = tcp_listener.local_addr().unwrap();
+        let ws_lobby = lobby.clone();
+        thread::spawn(move || serve(ws_listener, ws_lobby));
+        thread::spawn(move || server::serve(tcp_listener, lobby));
+
+        let (mut browser, _) = tungstenite::client(format!("ws://{}/", ws_addr), TcpStream::connect(ws_addr).unwrap()).unwrap();
+        browser.send(Message::Text(String::from("NEWMATCH"))).unwrap();
+        assert_eq!(text(&mut browser), "Match 1 created. Waiting for opponent.");
+
+        let mut tcp = TcpStream::connect(tcp_addr).unwrap();
+        let mut reader = BufReader::new(tcp.try_clone().unwrap());
+        tcp.write_all(b"JOIN 1\n").unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "Joined match 1. You are player 2.\n");
+        assert_eq!(text(&mut browser), "JOINED 1 2");
+
+        tcp.write_all(b"SAY hello browser\n").unwrap();
+        assert_eq!(text(&mut browser), "CHAT 1 2 hello browser");
+        browser.send(Message::Text(String::from("QUIT"))).unwrap();
+        assert!(matches!(browser.read(), Ok(Message::Close(_)) | Ok(Message::Text(_))));
+    }
+
+    #[test]
+    fn refused_addresses_get_no_handshake() {
+        let lobby = Lobby::shared();
+        let limits = Limits{max_connections_per_ip: 1, ..Limits::default()};
+        lobby.lock().unwrap().apply_config(&Config{limits, ..Config::default()});
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, lobby));
+
+        let (_browser, _) = tungstenite::client(format!("ws://{}/", addr), TcpStream::connect(addr).unwrap()).unwrap();
+        let mut second = TcpStream::connect(addr).unwrap();
+        second.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(second.read(&mut buf).unwrap(), 0);
+    }
+}
@@ -0,0 +1,114 @@
+// A server on an ephemeral port and clients that speak the line protocol to
+// it, failing the test with the line they got whenever it is not the one expected.
+
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use libbattleship::config::Config;
+use libbattleship::limits::Limits;
+use libbattleship::server::{self, Lobby, SharedLobby};
+
+
+// Longest wait for a line before the test fails.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+
+pub struct Server {
+    addr: SocketAddr,
+    pub lobby: SharedLobby,
+}
+
+impl Server {
+    pub fn start() -> Server {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let lobby = Lobby::shared();
+        // Scripts fire far faster than anybody types.
+        let limits = Limits{commands_per_second: 1000.0, command_burst: 1000.0, ..Limits::default()};
+        lobby.lock().unwrap().apply_config(&Config{limits, ..Config::default()});
+        let served = lobby.clone();
+        thread::spawn(move || server::serve(listener, served));
+        Server{addr, lobby}
+    }
+
+    pub fn connect(&self, name: &str) -> Client {
+        let stream = TcpStream::connect(self.addr).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        Client{name: name.to_string(), reader: BufReader::new(stream.try_clone().unwrap()), stream}
+    }
+}
+
+
+pub struct Client {
+    name: String,  // Shown when a test fails
+    reader: BufReader<TcpStream>,
+    stream: TcpStream,
+}
+
+impl Client {
+    pub fn send(&mut self, line: &str) {
+        writeln!(self.stream, "{}", line).unwrap();
+    }
+
+    // The next line, or None once the server has closed the connection.
+    pub fn read(&mut self) -> Option<String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(line.trim_end_matches('\n').to_string()),
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                panic!("{}: nothing from the server in {:?}", self.name, TIMEOUT)
+            }
+            Err(e) => panic!("{}: {}", self.name, e)
+        }
+    }
+
+    pub fn expect(&mut self, expected: &str) {
+        let line = self.read();
+        assert_eq!(line.as_deref(), Some(expected), "{} got the wrong line", self.name);
+    }
+
+    pub fn expect_all(&mut self, expected: &[&str]) {
+        for line in expected {
+            self.expect(line);
+        }
+    }
+
+    // Sends a command and checks the whole answer.
+    pub fn call(&mut self, line: &str, expected: &[&str]) {
+        self.send(line);
+        self.expect_all(expected);
+    }
+
+    pub fn expect_closed(&mut self) {
+        let line = self.read();
+        assert_eq!(line, None, "{} should have been disconnected", self.name);
+    }
+
+    // Drops the connection without a word, as a crashed client would.
+    pub fn hang_up(self) {
+        self.stream.shutdown(std::net::Shutdown::Both).ok();
+    }
+}
+
+
+// CELL command for board notation such as "C4".
+pub fn cell(coord: &str) -> String {
+    let x = coord.as_bytes()[0] - b'A' + 1;
+    format!("CELL:[{},{}]", x, &coord[1..])
+}
+
+// A FOG board with the given hits ('X') and misses ('o'), everything else unknown.
+pub fn fog(hits: &[&str], misses: &[&str]) -> String {
+    let mut rows = vec![vec!['.'; 9]; 9];
+    for (coords, mark) in [(hits, 'X'), (misses, 'o')] {
+        for coord in coords.iter() {
+            let x = (coord.as_bytes()[0] - b'A') as usize;
+            let y: usize = coord[1..].parse().unwrap();
+            rows[y-1][x] = mark;
+        }
+    }
+    rows.iter().map(|row| row.iter().collect::<String>()).collect::<Vec<_>>().join("/")
+}
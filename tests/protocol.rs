@@ -0,0 +1,166 @@
+// End-to-end protocol checks: scripted clients play whole games against a
+// server on an ephemeral port, and every line they get back is compared
+// exactly, so any change to what goes over the wire shows up here.
+
+mod common;
+
+use common::{cell, fog, Server};
+
+
+// Both players set up the same fleet along the top left corner.
+const FLEET: [&str; 4] = ["PLACE C5 A1 Right", "PLACE H4 A2 Right", "PLACE L3 A3 Right", "PLACE A2 A4 Right"];
+const FLEET_CELLS: [&str; 14] = ["A1", "B1", "C1", "D1", "E1", "A2", "B2", "C2", "D2", "A3", "B3", "C3", "A4", "B4"];
+const WATER: [&str; 13] = ["I1", "I2", "I3", "I4", "I5", "I6", "I7", "I8", "I9", "H1", "H2", "H3", "H4"];
+
+
+#[test]
+fn solo_game_is_played_to_the_end() {
+    let server = Server::start();
+    let mut ann = server.connect("ann");
+    ann.call("HELLO", &["Nothing to do"]);
+    ann.call("CELL:[1,1]", &["No game started yet."]);
+    ann.call("STARTGAME", &["Starting new game. Game ID: 1"]);
+
+    // Row by row until the last ship goes down.
+    let mut shots = 0;
+    let mut hits = 0;
+    'board: for y in 1..=9 {
+        for x in 1..=9 {
+            ann.send(&format!("CELL:[{},{}]", x, y));
+            shots += 1;
+            match ann.read().unwrap().as_str() {
+                "HIT" => hits += 1,
+                "MISS" => {}
+                line => {
+                    assert_eq!(line, format!("Game successcully completed. Score {}", 81 - shots));
+                    break 'board
+                }
+            }
+        }
+    }
+    assert_eq!(hits, 13);
+    ann.expect_closed();
+}
+
+
+#[test]
+fn match_is_played_to_a_win() {
+    let server = Server::start();
+    let mut ann = server.connect("ann");
+    let mut bob = server.connect("bob");
+    let mut eve = server.connect("eve");
+
+    ann.call("NEWMATCH", &["Match 1 created. Waiting for opponent."]);
+    bob.call("JOIN 9", &["No such game."]);
+    bob.call("JOIN 1", &["Joined match 1. You are player 2.", "JOINED 1 2"]);
+    ann.expect("JOINED 1 2");
+    eve.call("JOIN 1", &["Game already full."]);
+
+    ann.call(&cell("A1"), &["Waiting for fleets to be placed."]);
+    ann.call("READY", &["Fleet incomplete, still to place: C5 H4 L3 A2."]);
+    ann.call(FLEET[0], &["C5 placed."]);
+    ann.call("PLACE C5 A5 Right", &["Ship already placed."]);
+    ann.call("PLACE H4 A1 Down", &["Collision with another ship."]);
+    ann.call("PLACE H4 G1 Right", &["Ship fell outside the board."]);
+    for (line, reply) in FLEET[1..].iter().zip(["H4 placed.", "L3 placed.", "A2 placed."]) {
+        ann.call(line, &[reply]);
+    }
+    ann.call("READY", &["READY"]);
+    ann.call(FLEET[0], &["Fleet already in place."]);
+    bob.expect("READY 1 1");
+    for (line, ship) in FLEET.iter().zip(["C5", "H4", "L3", "A2"]) {
+        bob.call(line, &[&format!("{} placed.", ship)]);
+    }
+    bob.call("READY", &["READY"]);
+    ann.expect("READY 1 2");
+
+    eve.call("WATCH 1", &["Watching game 1.", &format!("FOG 1 1 {}", fog(&[], &[])), &format!("FOG 1 2 {}", fog(&[], &[]))]);
+    bob.call(&cell("I9"), &["Not your turn."]);
+    ann.call("CELL:[0,5]", &["Not on the board."]);
+    ann.call("CELL:[9,0]", &["Not on the board."]);
+    bob.call(&cell("I9"), &["Not your turn."]);
+
+    // Ann never misses, Bob never hits.
+    for (i, target) in FLEET_CELLS.iter().enumerate() {
+        let last = i == FLEET_CELLS.len() - 1;
+        let shot = format!("SHOT 1 1 {} HIT", target);
+        let board = format!("FOG 1 2 {}", fog(&FLEET_CELLS[..=i], &[]));
+        if last {
+            ann.call(&cell(target), &["Game successcully completed. Score 67"]);
+            ann.expect_closed();
+            bob.expect_all(&[&shot, &board, "GAMEOVER 1 1"]);
+            let ann_fleet = fog(&FLEET_CELLS, &WATER).replace('X', "#");
+            eve.expect_all(&[&shot, &board, &format!("REVEAL 1 1 {}", ann_fleet),
+                             &format!("REVEAL 1 2 {}", fog(&FLEET_CELLS, &[])), "GAMEOVER 1 1"]);
+            break
+        }
+        ann.call(&cell(target), &["HIT"]);
+        bob.expect_all(&[&shot, &board]);
+        eve.expect_all(&[&shot, &board]);
+
+        let miss = WATER[i];
+        let shot = format!("SHOT 1 2 {} MISS", miss);
+        let board = format!("FOG 1 1 {}", fog(&[], &WATER[..=i]));
+        bob.call(&cell(miss), &["MISS"]);
+        ann.expect_all(&[&shot, &board]);
+        eve.expect_all(&[&shot, &board]);
+    }
+
+    bob.call(&cell("A1"), &["No game started yet."]);
+    bob.send("QUIT");
+    bob.expect_closed();
+}
+
+
+#[test]
+fn leaving_a_match_forfeits_it() {
+    let server = Server::start();
+    let mut ann = server.connect("ann");
+
+    // Quitting...
+    let mut bob = server.connect("bob");
+    ann.call("NEWMATCH", &["Match 1 created. Waiting for opponent."]);
+    bob.call("JOIN 1", &["Joined match 1. You are player 2.", "JOINED 1 2"]);
+    ann.expect("JOINED 1 2");
+    bob.send("QUIT");
+    bob.expect_closed();
+    ann.expect("GAMEOVER 1 1");
+
+    // ...or just going away.
+    let mut cas = server.connect("cas");
+    ann.call("NEWMATCH", &["Match 2 created. Waiting for opponent."]);
+    cas.call("JOIN 2", &["Joined match 2. You are player 2.", "JOINED 2 2"]);
+    ann.expect("JOINED 2 2");
+    cas.hang_up();
+    ann.expect("GAMEOVER 2 1");
+
+    // A match nobody joined simply goes away with its creator.
+    ann.call("NEWMATCH", &["Match 3 created. Waiting for opponent."]);
+    ann.send("QUIT");
+    ann.expect_closed();
+    let mut dan = server.connect("dan");
+    dan.call("JOIN 3", &["No such game."]);
+    assert!(server.lobby.lock().unwrap().get_games().is_empty());
+}
+
+
+#[test]
+fn players_chat_and_spectators_listen() {
+    let server = Server::start();
+    let mut ann = server.connect("ann");
+    let mut bob = server.connect("bob");
+    let mut eve = server.connect("eve");
+
+    ann.call("SAY hello", &["Not in a game."]);
+    ann.call("NEWMATCH", &["Match 1 created. Waiting for opponent."]);
+    eve.call("WATCH 1", &["Watching game 1.", &format!("FOG 1 1 {}", fog(&[], &[]))]);
+    bob.call("JOIN 1", &["Joined match 1. You are player 2.", "JOINED 1 2"]);
+    ann.expect("JOINED 1 2");
+    eve.expect_all(&["JOINED 1 2", &format!("FOG 1 1 {}", fog(&[], &[])), &format!("FOG 1 2 {}", fog(&[], &[]))]);
+
+    bob.call("SAY good luck", &["Message sent."]);
+    ann.expect("CHAT 1 2 good luck");
+    eve.expect("CHAT 1 2 good luck");
+    ann.call(&format!("SAY {}", "x".repeat(201)), &["Message too long (max 200 characters)."]);
+    eve.call("SAY let me play", &["Not in a game."]);
+}